@@ -3,22 +3,197 @@ use anyhow::Result;
 use repos::entity::Episode;
 use serde_json::json;
 
+/// delivery of a serif, merged into the AudioQuery of VOICEVOX compatible engines
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pitch_scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intonation_scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_scale: Option<f64>,
+    /// silence before the serif in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_phoneme_length: Option<f64>,
+    /// silence after the serif in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_phoneme_length: Option<f64>,
+}
+
+impl VoiceParams {
+    /// ranges accepted by the VOICEVOX editor
+    pub fn validate(&self) -> Result<()> {
+        let ranges = [
+            ("speedScale", self.speed_scale, 0.5..=2.0),
+            ("pitchScale", self.pitch_scale, -0.15..=0.15),
+            ("intonationScale", self.intonation_scale, 0.0..=2.0),
+            ("volumeScale", self.volume_scale, 0.0..=2.0),
+            ("prePhonemeLength", self.pre_phoneme_length, 0.0..=1.5),
+            ("postPhonemeLength", self.post_phoneme_length, 0.0..=1.5),
+        ];
+        for (name, value, range) in ranges {
+            if let Some(value) = value {
+                if !range.contains(&value) {
+                    anyhow::bail!(
+                        "{} must be in {}..={}: {}",
+                        name,
+                        range.start(),
+                        range.end(),
+                        value
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "type")]
 pub enum Section {
+    #[serde(rename_all = "camelCase")]
     Serif {
         speaker: String,
+        /// label of the speaker in subtitles, defaults to `speaker`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        speaker_name: Option<String>,
         text: String,
+        /// in dB, applied before mastering
+        #[serde(skip_serializing_if = "Option::is_none")]
+        gain: Option<f64>,
+        #[serde(flatten)]
+        params: VoiceParams,
     },
+    /// `url` is http(s), `file://` under the allowed root, or relative to the storage
+    #[serde(rename_all = "camelCase")]
     Audio {
         url: String,
+        /// in seconds of the source
         #[serde(skip_serializing_if = "Option::is_none")]
         from: Option<f64>,
         #[serde(skip_serializing_if = "Option::is_none")]
         to: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        gain: Option<f64>,
+        /// in seconds, a short fade is applied at cut points by default
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fade_in: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fade_out: Option<f64>,
+    },
+    /// pause in seconds
+    Silence { duration: f64 },
+    Jingle {
+        url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        gain: Option<f64>,
+    },
+    /// starts a chapter, takes no time by itself
+    Chapter { title: String },
+    /// loops under the following sections, takes no time by itself
+    #[serde(rename_all = "camelCase")]
+    Bgm {
+        url: String,
+        #[serde(default = "default_bgm_volume")]
+        volume: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fade_in: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fade_out: Option<f64>,
+        /// lowers the bgm while someone speaks
+        #[serde(default)]
+        duck_under_speech: bool,
+        /// number of following sections to play under,
+        /// `None` plays until the next bgm or the end
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sections: Option<usize>,
     },
 }
 
+fn default_bgm_volume() -> f64 {
+    0.3
+}
+
+impl Section {
+    pub fn gain(&self) -> Option<f64> {
+        match self {
+            Section::Serif { gain, .. }
+            | Section::Audio { gain, .. }
+            | Section::Jingle { gain, .. } => *gain,
+            Section::Silence { .. } | Section::Chapter { .. } | Section::Bgm { .. } => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(gain) = self.gain() {
+            if !(-30.0..=30.0).contains(&gain) {
+                anyhow::bail!("gain must be in -30..=30: {}", gain);
+            }
+        }
+        match self {
+            Section::Serif { params, .. } => params.validate(),
+            Section::Silence { duration } => {
+                if !(0.0..=600.0).contains(duration) {
+                    anyhow::bail!("duration must be in 0..=600: {}", duration);
+                }
+                Ok(())
+            }
+            Section::Bgm {
+                volume,
+                fade_in,
+                fade_out,
+                ..
+            } => {
+                if !(0.0..=1.0).contains(volume) {
+                    anyhow::bail!("volume must be in 0..=1: {}", volume);
+                }
+                if [fade_in, fade_out]
+                    .into_iter()
+                    .flatten()
+                    .any(|fade| *fade < 0.0)
+                {
+                    anyhow::bail!("fadeIn and fadeOut must not be negative");
+                }
+                Ok(())
+            }
+            Section::Chapter { title } => {
+                if title.trim().is_empty() {
+                    anyhow::bail!("chapter title is empty");
+                }
+                Ok(())
+            }
+            Section::Audio {
+                from,
+                to,
+                fade_in,
+                fade_out,
+                ..
+            } => {
+                if from.is_some_and(|from| from < 0.0) {
+                    anyhow::bail!("from must not be negative");
+                }
+                if let (Some(from), Some(to)) = (from, to) {
+                    if from >= to {
+                        anyhow::bail!("from must be before to: {}..{}", from, to);
+                    }
+                }
+                if [fade_in, fade_out]
+                    .into_iter()
+                    .flatten()
+                    .any(|fade| *fade < 0.0)
+                {
+                    anyhow::bail!("fadeIn and fadeOut must not be negative");
+                }
+                Ok(())
+            }
+            Section::Jingle { .. } => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NewEpisode {
@@ -65,3 +240,61 @@ impl ApiClient {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serif_round_trip() -> Result<()> {
+        let section = json!({
+            "type": "Serif",
+            "speaker": "voicevox:3",
+            "text": "こんにちは",
+            "speedScale": 1.2,
+            "postPhonemeLength": 0.5,
+        });
+        let serif: Section = serde_json::from_value(section.clone())?;
+        let Section::Serif { ref params, .. } = serif else {
+            panic!("not a serif");
+        };
+        assert_eq!(params.speed_scale, Some(1.2));
+        assert_eq!(params.pitch_scale, None);
+        assert_eq!(serde_json::to_value(&serif)?, section);
+        serif.validate()?;
+
+        let invalid: Section = serde_json::from_value(json!({
+            "type": "Serif",
+            "speaker": "3",
+            "text": "こんにちは",
+            "speedScale": 3.0,
+        }))?;
+        assert!(invalid.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bgm_defaults() -> Result<()> {
+        let bgm: Section = serde_json::from_value(json!({
+            "type": "Bgm",
+            "url": "https://example.com/bgm.mp3",
+            "fadeIn": 2.0,
+            "duckUnderSpeech": true,
+        }))?;
+        let Section::Bgm {
+            volume,
+            fade_in,
+            duck_under_speech,
+            sections,
+            ..
+        } = bgm
+        else {
+            panic!("not a bgm");
+        };
+        assert_eq!(
+            (volume, fade_in, duck_under_speech, sections),
+            (0.3, Some(2.0), true, None)
+        );
+        Ok(())
+    }
+}
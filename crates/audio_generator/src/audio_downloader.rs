@@ -1,18 +1,394 @@
-use crate::{ffmpeg::slice_audio, workdir::WorkDir, AudioGenerator};
+use crate::{
+    cache::AudioCache,
+    env_or,
+    error::AudioError,
+    ffmpeg::decode_audio,
+    pcm::{blocking, is_wav, Pcm},
+    workdir::WorkDir,
+    AudioGenerator, Clip,
+};
 use api::episode::Section;
 use async_trait::async_trait;
-use std::path::PathBuf;
-use tokio::fs;
+use reqwest::header::{
+    HeaderMap, HeaderName, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// fade at cut points to avoid clicks, in seconds
+const CUT_FADE: f64 = 0.02;
+
+/// limits and locations of audio sources
+#[derive(Debug, Clone)]
+pub(crate) struct SourceConfig {
+    pub(crate) max_bytes: u64,
+    /// per attempt
+    pub(crate) timeout: Duration,
+    pub(crate) retries: u32,
+    /// `file://` urls are allowed only under this directory
+    pub(crate) local_root: Option<PathBuf>,
+    /// base of urls without a scheme, e.g. `bgm/opening.mp3`
+    pub(crate) storage_url: Option<String>,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 200 * 1024 * 1024,
+            timeout: Duration::from_secs(120),
+            retries: 2,
+            local_root: None,
+            storage_url: None,
+        }
+    }
+}
+
+impl SourceConfig {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            max_bytes: env_or("AUDIO_SOURCE_MAX_BYTES", default.max_bytes)?,
+            timeout: Duration::from_secs(env_or(
+                "AUDIO_SOURCE_TIMEOUT_SEC",
+                default.timeout.as_secs(),
+            )?),
+            retries: env_or("AUDIO_SOURCE_RETRIES", default.retries)?,
+            // NOTE: an empty root would allow any file
+            local_root: std::env::var("AUDIO_SOURCE_LOCAL_ROOT")
+                .ok()
+                .filter(|root| !root.is_empty())
+                .map(PathBuf::from),
+            storage_url: std::env::var("STORAGE_PUBLIC_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Source {
+    Http(String),
+    Local(PathBuf),
+}
+
+impl SourceConfig {
+    fn resolve(&self, url: &str) -> Result<Source, AudioError> {
+        let invalid = |reason: &str| AudioError::InvalidSource {
+            url: url.to_string(),
+            reason: reason.to_string(),
+        };
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Source::Http(url.to_string()));
+        }
+        if let Some(path) = url.strip_prefix("file://") {
+            let root = self
+                .local_root
+                .as_ref()
+                .ok_or_else(|| invalid("local files are not allowed"))?;
+            let path = PathBuf::from(path);
+            // NOTE: checked lexically, so `..` must not escape the root
+            if path.components().any(|c| c == Component::ParentDir) || !path.starts_with(root) {
+                return Err(invalid("outside of the allowed directory"));
+            }
+            return Ok(Source::Local(path));
+        }
+        if url.contains("://") {
+            return Err(invalid("unsupported scheme"));
+        }
+        let base = self
+            .storage_url
+            .as_ref()
+            .ok_or_else(|| invalid("STORAGE_PUBLIC_URL is not set"))?;
+        Ok(Source::Http(format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            url.trim_start_matches('/')
+        )))
+    }
+
+    /// the real path of a local source, which must not lead out of the root by symlinks either
+    async fn real_path(&self, url: &str, path: &Path) -> Result<PathBuf, AudioError> {
+        let root = self
+            .local_root
+            .as_ref()
+            .expect("local sources are resolved with a root");
+        let root = fs::canonicalize(root).await?;
+        let path = fs::canonicalize(path).await?;
+        if !path.starts_with(&root) {
+            return Err(AudioError::InvalidSource {
+                url: url.to_string(),
+                reason: "outside of the allowed directory".to_string(),
+            });
+        }
+        Ok(path)
+    }
+}
+
+/// validators of a cached source to revalidate it with
+#[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: HeaderName| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// extension of a known audio container from the first bytes
+pub(crate) fn sniff_format(head: &[u8]) -> Option<&'static str> {
+    match head {
+        _ if is_wav(head) => Some("wav"),
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        // MPEG audio frame sync
+        [0xff, b, ..] if b & 0xe0 == 0xe0 => Some("mp3"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some("m4a"),
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => Some("webm"),
+        _ => None,
+    }
+}
+
+/// `(fade in, fade out)`, a short fade is applied where the source is cut
+fn fades(
+    from: Option<f64>,
+    to: Option<f64>,
+    fade_in: Option<f64>,
+    fade_out: Option<f64>,
+) -> (f64, f64) {
+    let cut = |cut: Option<f64>| cut.map(|_| CUT_FADE).unwrap_or(0.0);
+    (fade_in.unwrap_or(cut(from)), fade_out.unwrap_or(cut(to)))
+}
+
+/// whether the response is likely an error page rather than a file
+fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/") || content_type.starts_with("application/json")
+}
 
 pub(crate) struct AudioDownloader {
     client: reqwest::Client,
+    config: SourceConfig,
+    cache: AudioCache,
 }
 
 impl AudioDownloader {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(config: SourceConfig) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client: reqwest::Client::builder()
+                .user_agent(std::env::var("USER_AGENT").unwrap_or_default())
+                .build()
+                .expect("Failed to build HTTP client"),
+            config,
+            cache: AudioCache::from_env(),
+        }
+    }
+
+    /// streams the body into `out` up to `max_bytes`, returns its validators.
+    /// `None` if it is not modified since `cached`, leaving `out` as is
+    async fn fetch(
+        &self,
+        url: &str,
+        out: &Path,
+        cached: Option<&Validators>,
+    ) -> Result<Option<Validators>, AudioError> {
+        let fetch_error = |source| AudioError::Fetch {
+            url: url.to_string(),
+            source,
+        };
+        let too_large = || AudioError::SourceTooLarge {
+            url: url.to_string(),
+            limit: self.config.max_bytes,
+        };
+        let mut req = self.client.get(url);
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let mut res = req
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(fetch_error)?;
+        if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
         }
+        if res
+            .content_length()
+            .is_some_and(|len| len > self.config.max_bytes)
+        {
+            return Err(too_large());
+        }
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if is_text(content_type) {
+            return Err(AudioError::InvalidSource {
+                url: url.to_string(),
+                reason: format!("unexpected content type {}", content_type),
+            });
+        }
+        let validators = Validators::from_headers(res.headers());
+        // NOTE: written aside, so a failed attempt leaves a cached copy in `out` intact
+        let partial = out.with_extension("partial");
+        let mut file = fs::File::create(&partial).await?;
+        let mut len = 0;
+        while let Some(chunk) = res.chunk().await.map_err(fetch_error)? {
+            len += chunk.len() as u64;
+            if len > self.config.max_bytes {
+                return Err(too_large());
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        fs::rename(&partial, out).await?;
+        Ok(Some(validators))
+    }
+
+    /// retries timeouts, network errors and server errors
+    async fn fetch_with_retry(
+        &self,
+        url: &str,
+        out: &Path,
+        cached: Option<&Validators>,
+        cancel: &CancellationToken,
+    ) -> Result<Option<Validators>, AudioError> {
+        let mut attempt = 0;
+        loop {
+            let res = tokio::select! {
+                res = tokio::time::timeout(self.config.timeout, self.fetch(url, out, cached)) => {
+                    res.unwrap_or(Err(AudioError::FetchTimeout {
+                        url: url.to_string(),
+                        timeout: self.config.timeout,
+                    }))
+                }
+                _ = cancel.cancelled() => return Err(AudioError::Cancelled("fetch")),
+            };
+            let retryable = match &res {
+                Err(AudioError::FetchTimeout { .. }) => true,
+                Err(AudioError::Fetch { source, .. }) => source.status().map_or(true, |status| {
+                    status.is_server_error() || status.as_u16() == 429
+                }),
+                _ => false,
+            };
+            if !retryable || attempt >= self.config.retries {
+                return res;
+            }
+            attempt += 1;
+            tracing::warn!("Retrying {} ({}): {:?}", url, attempt, res);
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1 << attempt)) => {}
+                _ = cancel.cancelled() => return Err(AudioError::Cancelled("fetch")),
+            }
+        }
+    }
+
+    /// saves the source as is, returns its path.
+    /// remote sources are cached by url and revalidated by their etag or last modified,
+    /// those without either are fetched every time
+    pub(crate) async fn download(
+        &self,
+        work_dir: &WorkDir,
+        url: &str,
+        extension: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<PathBuf> {
+        let path = work_dir
+            .dir()
+            .join(format!("{}.{}", Uuid::new_v4(), extension));
+        match self.config.resolve(url)? {
+            Source::Local(source) => {
+                let source = self.config.real_path(url, &source).await?;
+                if fs::metadata(&source).await?.len() > self.config.max_bytes {
+                    return Err(AudioError::SourceTooLarge {
+                        url: url.to_string(),
+                        limit: self.config.max_bytes,
+                    }
+                    .into());
+                }
+                fs::copy(&source, &path).await?;
+            }
+            Source::Http(url) => {
+                let hash = hex::encode(Sha256::digest(url.as_bytes()));
+                let cached = match self.cache.read(&hash, "source.json").await? {
+                    Some(validators) if self.cache.get(&hash, "source", &path).await? => {
+                        serde_json::from_slice::<Validators>(&validators).ok()
+                    }
+                    _ => None,
+                };
+                match self
+                    .fetch_with_retry(&url, &path, cached.as_ref(), cancel)
+                    .await?
+                {
+                    Some(validators) if !validators.is_empty() => {
+                        // NOTE: the source is replaced before its validators,
+                        // so stale validators only cause a refetch
+                        let validators_path = path.with_extension("source.json");
+                        fs::write(&validators_path, serde_json::to_vec(&validators)?).await?;
+                        self.cache.put(&hash, "source", &path).await?;
+                        self.cache
+                            .put(&hash, "source.json", &validators_path)
+                            .await?;
+                        fs::remove_file(&validators_path).await?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    /// downloads an audio and names it by its format
+    pub(crate) async fn download_audio(
+        &self,
+        work_dir: &WorkDir,
+        url: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<PathBuf> {
+        let downloaded = self.download(work_dir, url, "source", cancel).await?;
+        let mut head = [0; 12];
+        let n = fs::File::open(&downloaded).await?.read(&mut head).await?;
+        let Some(extension) = sniff_format(&head[..n]) else {
+            fs::remove_file(&downloaded).await?;
+            return Err(AudioError::InvalidSource {
+                url: url.to_string(),
+                reason: "unknown audio format".to_string(),
+            }
+            .into());
+        };
+        let path = downloaded.with_extension(extension);
+        fs::rename(&downloaded, &path).await?;
+        Ok(path)
     }
 }
 
@@ -20,22 +396,133 @@ impl AudioDownloader {
 impl AudioGenerator for AudioDownloader {
     async fn generate(
         &self,
-        i: &mut usize,
         work_dir: &WorkDir,
         section: Section,
-    ) -> anyhow::Result<Vec<(PathBuf, String)>> {
-        let Section::Audio { url, from, to } = section else {
-            return Err(anyhow::anyhow!("Invalid segment"));
-        };
-        let response = self.client.get(url).send().await?;
-        let audio = response.bytes().await?;
-        let audio_file_path = work_dir.dir().join("tmp.mp3");
-        std::fs::write(&audio_file_path, &audio)?;
-
-        let sliced_audio_file_path = work_dir.dir().join(&format!("{}.wav", i));
-        *i += 1;
-        slice_audio(&audio_file_path, &sliced_audio_file_path, from, to).await?;
-        fs::remove_file(&audio_file_path).await?;
-        Ok(vec![(sliced_audio_file_path, "♪".to_string())])
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<Clip>> {
+        let (url, from, to, fade_in, fade_out) = match section {
+            Section::Audio {
+                url,
+                from,
+                to,
+                fade_in,
+                fade_out,
+                ..
+            } => (url, from, to, fade_in, fade_out),
+            Section::Jingle { url, .. } => (url, None, None, None, None),
+            _ => return Err(anyhow::anyhow!("Invalid segment")),
+        };
+        let source = self.download_audio(work_dir, &url, cancel).await?;
+
+        // NOTE: only compressed audios need ffmpeg
+        let wav_path = source.with_extension("wav");
+        if source != wav_path {
+            decode_audio(&source, &wav_path, cancel).await?;
+            fs::remove_file(&source).await?;
+        }
+        let (fade_in, fade_out) = fades(from, to, fade_in, fade_out);
+        if from.is_some() || to.is_some() || fade_in > 0.0 || fade_out > 0.0 {
+            let path = wav_path.clone();
+            blocking(move || {
+                Pcm::read(&path)?
+                    .slice(from, to)?
+                    .fade(fade_in, fade_out)
+                    .write(&path)
+            })
+            .await?;
+        }
+        Ok(vec![Clip::new(wav_path, "♪".to_string())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_resolve() {
+        let config = SourceConfig {
+            local_root: Some(PathBuf::from("/srv/audio")),
+            storage_url: Some("https://cdn.example.com/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolve("https://example.com/a.mp3").ok(),
+            Some(Source::Http("https://example.com/a.mp3".to_string()))
+        );
+        assert_eq!(
+            config.resolve("/bgm/a.mp3").ok(),
+            Some(Source::Http(
+                "https://cdn.example.com/bgm/a.mp3".to_string()
+            ))
+        );
+        assert_eq!(
+            config.resolve("file:///srv/audio/a.wav").ok(),
+            Some(Source::Local(PathBuf::from("/srv/audio/a.wav")))
+        );
+        assert!(config
+            .resolve("file:///srv/audio/../../etc/passwd")
+            .is_err());
+        assert!(config.resolve("file:///etc/passwd").is_err());
+        assert!(config.resolve("ftp://example.com/a.mp3").is_err());
+        assert!(SourceConfig::default()
+            .resolve("file:///srv/audio/a.wav")
+            .is_err());
+        assert!(SourceConfig::default().resolve("bgm/a.mp3").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_real_path() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("sources-{}", Uuid::new_v4()));
+        let root = dir.join("root");
+        fs::create_dir_all(&root).await?;
+        fs::write(root.join("a.wav"), b"a").await?;
+        fs::write(dir.join("secret.wav"), b"b").await?;
+        std::os::unix::fs::symlink(dir.join("secret.wav"), root.join("b.wav"))?;
+        let config = SourceConfig {
+            local_root: Some(root.clone()),
+            ..Default::default()
+        };
+        assert!(config.real_path("a", &root.join("a.wav")).await.is_ok());
+        assert!(config.real_path("b", &root.join("b.wav")).await.is_err());
+        fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validators() {
+        let mut headers = HeaderMap::new();
+        assert!(Validators::from_headers(&headers).is_empty());
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        assert_eq!(
+            Validators::from_headers(&headers),
+            Validators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sniff_format() {
+        assert_eq!(sniff_format(b"ID3\x04\x00"), Some("mp3"));
+        assert_eq!(sniff_format(&[0xff, 0xfb, 0x90, 0x00]), Some("mp3"));
+        assert_eq!(sniff_format(b"OggS\x00\x02"), Some("ogg"));
+        assert_eq!(sniff_format(b"\x00\x00\x00\x20ftypM4A "), Some("m4a"));
+        assert_eq!(sniff_format(b"RIFF\x24\x00\x00\x00WAVE"), Some("wav"));
+        assert_eq!(sniff_format(b"<!DOCTYPE html>"), None);
+        assert_eq!(sniff_format(b""), None);
+    }
+
+    #[test]
+    fn test_fades() {
+        assert_eq!(fades(None, None, None, None), (0.0, 0.0));
+        assert_eq!(fades(Some(10.0), None, None, None), (CUT_FADE, 0.0));
+        assert_eq!(
+            fades(Some(10.0), Some(20.0), None, Some(2.0)),
+            (CUT_FADE, 2.0)
+        );
     }
 }
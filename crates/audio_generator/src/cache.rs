@@ -0,0 +1,100 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// identifies a synthesized clip by everything which affects its audio
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct CacheKey<'a> {
+    pub(crate) engine: &'a str,
+    pub(crate) engine_version: &'a str,
+    pub(crate) speaker: &'a str,
+    pub(crate) text: &'a str,
+    pub(crate) params: &'a serde_json::Value,
+}
+
+impl CacheKey<'_> {
+    pub(crate) fn hash(&self) -> String {
+        let key = serde_json::to_vec(self).expect("CacheKey is serializable");
+        hex::encode(Sha256::digest(key))
+    }
+}
+
+/// content addressed store of clips shared across tasks
+#[derive(Debug, Clone)]
+pub(crate) struct AudioCache {
+    dir: PathBuf,
+}
+
+impl AudioCache {
+    pub(crate) fn from_env() -> Self {
+        let dir = std::env::var("AUDIO_CACHE_DIR").unwrap_or("temp/cache".to_string());
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    fn path(&self, hash: &str, extension: &str) -> PathBuf {
+        self.dir
+            .join(&hash[..2])
+            .join(format!("{}.{}", hash, extension))
+    }
+
+    /// copies the cached file to `out`, returns `false` on miss
+    pub(crate) async fn get(
+        &self,
+        hash: &str,
+        extension: &str,
+        out: &Path,
+    ) -> anyhow::Result<bool> {
+        let path = self.path(hash, extension);
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(false);
+        }
+        tokio::fs::copy(&path, out).await?;
+        Ok(true)
+    }
+
+    /// the content of a cached file, `None` on miss
+    pub(crate) async fn read(
+        &self,
+        hash: &str,
+        extension: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(hash, extension)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn put(&self, hash: &str, extension: &str, file: &Path) -> anyhow::Result<()> {
+        let path = self.path(hash, extension);
+        let dir = path.parent().expect("cache path has a parent");
+        tokio::fs::create_dir_all(dir).await?;
+        // NOTE: written aside and renamed, so readers never see a partial file
+        let tmp = dir.join(format!("{}.tmp", Uuid::new_v4()));
+        tokio::fs::copy(file, &tmp).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_key() {
+        let params = json!({});
+        let key = |text| CacheKey {
+            engine: "voicevox",
+            engine_version: "0.14.0",
+            speaker: "1",
+            text,
+            params: &params,
+        };
+        assert_eq!(key("こんにちは").hash(), key("こんにちは").hash());
+        assert_ne!(key("こんにちは").hash(), key("こんばんは").hash());
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+/// replaces words with their readings before synthesis
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    /// (surface, reading), longest surface first
+    words: Vec<(Vec<char>, String)>,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+impl Dictionary {
+    /// a later entry of the same surface overrides an earlier one
+    pub fn new(words: impl IntoIterator<Item = (String, String)>) -> Self {
+        let words: BTreeMap<String, String> = words
+            .into_iter()
+            .filter(|(surface, _)| !surface.is_empty())
+            .collect();
+        let mut words: Vec<(Vec<char>, String)> = words
+            .into_iter()
+            .map(|(surface, reading)| (surface.chars().collect(), reading))
+            .collect();
+        words.sort_by_key(|(surface, _)| std::cmp::Reverse(surface.len()));
+        Self { words }
+    }
+
+    /// alphanumeric surfaces only match whole words, so `AI` does not match in `MAIL`
+    fn matches(&self, text: &[char], i: usize, surface: &[char]) -> bool {
+        if !text[i..].starts_with(surface) {
+            return false;
+        }
+        let end = i + surface.len();
+        let bounded_start = !is_word_char(surface[0]) || i == 0 || !is_word_char(text[i - 1]);
+        let bounded_end = !is_word_char(surface[surface.len() - 1])
+            || end == text.len()
+            || !is_word_char(text[end]);
+        bounded_start && bounded_end
+    }
+
+    /// replaces from left to right preferring longer surfaces, readings are not replaced again
+    pub fn apply(&self, text: &str) -> String {
+        if self.words.is_empty() {
+            return text.to_string();
+        }
+        let text: Vec<char> = text.chars().collect();
+        let mut res = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < text.len() {
+            match self
+                .words
+                .iter()
+                .find(|(surface, _)| self.matches(&text, i, surface))
+            {
+                Some((surface, reading)) => {
+                    res.push_str(reading);
+                    i += surface.len();
+                }
+                None => {
+                    res.push(text[i]);
+                    i += 1;
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let dictionary = Dictionary::new([
+            ("AI".to_string(), "エーアイ".to_string()),
+            ("OpenAI".to_string(), "オープンエーアイ".to_string()),
+            ("GitHub".to_string(), "ギットハブ".to_string()),
+            ("GitHub".to_string(), "ギットハブ2".to_string()),
+        ]);
+        assert_eq!(
+            dictionary.apply("OpenAIとAIのMAILをGitHubで"),
+            "オープンエーアイとエーアイのMAILをギットハブ2で"
+        );
+        assert_eq!(Dictionary::default().apply("AI"), "AI");
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    #[error("ffmpeg is not available: {0}")]
+    FfmpegNotFound(std::io::Error),
+    #[error("ffmpeg {found} is older than {required}")]
+    FfmpegTooOld { found: String, required: String },
+    #[error("ffmpeg failed to {step} ({status}): {stderr}")]
+    Ffmpeg {
+        step: &'static str,
+        status: std::process::ExitStatus,
+        /// the tail of stderr
+        stderr: String,
+    },
+    #[error("Cancelled while running ffmpeg to {0}")]
+    Cancelled(&'static str),
+    #[error("Invalid wav {path}: {source}")]
+    Wav {
+        path: PathBuf,
+        source: wavers::WaversError,
+    },
+    #[error("Invalid range {from:?}..{to:?} of {duration:?}")]
+    InvalidRange {
+        from: Option<f64>,
+        to: Option<f64>,
+        duration: std::time::Duration,
+    },
+    #[error("Invalid source {url}: {reason}")]
+    InvalidSource { url: String, reason: String },
+    #[error("Source {url} exceeds {limit} bytes")]
+    SourceTooLarge { url: String, limit: u64 },
+    #[error("Failed to fetch {url}: {source}")]
+    Fetch { url: String, source: reqwest::Error },
+    #[error("Timed out fetching {url} in {timeout:?}")]
+    FetchTimeout {
+        url: String,
+        timeout: std::time::Duration,
+    },
+    #[error("IO: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Task: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
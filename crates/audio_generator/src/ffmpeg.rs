@@ -1,109 +1,265 @@
-use super::workdir::WorkDir;
+use crate::{
+    error::AudioError,
+    pcm::{CHANNELS, SAMPLE_RATE},
+    workdir::WorkDir,
+};
 use std::{
-    fs::OpenOptions,
-    io::Write,
+    ffi::OsStr,
     path::{Path, PathBuf},
+    process::Output,
     time::Duration,
 };
 use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
 use tracing::instrument;
-use wavers::Wav;
 
-pub(crate) fn get_duration(wav: &Wav<i16>) -> Duration {
-    let data_size = wav.header().data().size;
+/// oldest version with `loudnorm` linear mode and `sidechaincompress` we rely on
+const REQUIRED_VERSION: (u32, u32) = (4, 4);
+
+/// keeps the end of stderr, where ffmpeg reports errors
+fn tail(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines = stderr.lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(20)..].join("\n")
+}
+
+/// runs ffmpeg to do `step`, fails unless it exits successfully.
+/// the child process is killed when cancelled or dropped
+pub(crate) async fn ffmpeg<I, S>(
+    step: &'static str,
+    args: I,
+    cancel: &CancellationToken,
+) -> Result<Output, AudioError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-hide_banner").args(args).kill_on_drop(true);
+    let output = tokio::select! {
+        res = cmd.output() => res.map_err(AudioError::FfmpegNotFound)?,
+        _ = cancel.cancelled() => return Err(AudioError::Cancelled(step)),
+    };
+    if !output.status.success() {
+        return Err(AudioError::Ffmpeg {
+            step,
+            status: output.status,
+            stderr: tail(&output.stderr),
+        });
+    }
+    Ok(output)
+}
 
-    let sample_rate = wav.sample_rate() as u32;
-    let n_channels = wav.n_channels() as u32;
-    let bytes_per_sample = (wav.header().fmt_chunk.bits_per_sample / 8) as u32;
+/// `(major, minor)` from the first line of `ffmpeg -version`, `None` for git builds
+fn parse_version(output: &str) -> Option<(u32, u32)> {
+    let version = output
+        .lines()
+        .next()?
+        .strip_prefix("ffmpeg version ")?
+        .split_whitespace()
+        .next()?
+        .trim_start_matches('n');
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()
+        .and_then(|minor| minor.parse().ok())
+        .unwrap_or(0);
+    Some((major, minor))
+}
 
-    Duration::from_secs_f32(data_size as f32 / (sample_rate * n_channels * bytes_per_sample) as f32)
+/// checks ffmpeg is installed and recent enough, returns its version line
+pub async fn check_ffmpeg() -> Result<String, AudioError> {
+    let output = Command::new("ffmpeg")
+        .arg("-version")
+        .output()
+        .await
+        .map_err(AudioError::FfmpegNotFound)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next().unwrap_or_default().to_string();
+    match parse_version(&stdout) {
+        Some(version) if version < REQUIRED_VERSION => Err(AudioError::FfmpegTooOld {
+            found: line,
+            required: format!("{}.{}", REQUIRED_VERSION.0, REQUIRED_VERSION.1),
+        }),
+        Some(_) => Ok(line),
+        None => {
+            tracing::warn!("Unknown ffmpeg version: {}", line);
+            Ok(line)
+        }
+    }
 }
 
-pub(crate) async fn slice_audio(
+/// decodes a compressed audio such as mp3 into a wav of the speech format
+pub(crate) async fn decode_audio(
     input: &Path,
     output: &Path,
-    from_sec: Option<f64>,
-    to_sec: Option<f64>,
-) -> anyhow::Result<()> {
-    let mut cmd = Command::new("ffmpeg");
-    let mut args = vec!["-i".to_string(), input.display().to_string()];
-    if let (Some(from_sec), Some(to_sec)) = (from_sec, to_sec) {
+    cancel: &CancellationToken,
+) -> Result<(), AudioError> {
+    ffmpeg(
+        "decode audio",
+        [
+            "-y",
+            "-i",
+            input.display().to_string().as_str(),
+            "-vn",
+            "-acodec",
+            "pcm_s16le",
+            "-ar",
+            SAMPLE_RATE.to_string().as_str(),
+            "-ac",
+            CHANNELS.to_string().as_str(),
+            output.display().to_string().as_str(),
+        ],
+        cancel,
+    )
+    .await?;
+    Ok(())
+}
+
+/// a looped bgm placed on the speech
+#[derive(Debug, Clone)]
+pub(crate) struct BgmTrack {
+    pub(crate) path: PathBuf,
+    pub(crate) start: Duration,
+    pub(crate) duration: Duration,
+    pub(crate) volume: f64,
+    pub(crate) fade_in: Option<f64>,
+    pub(crate) fade_out: Option<f64>,
+    pub(crate) duck_under_speech: bool,
+}
+
+/// filtergraph mixing the speech `[0:a]` and bgms `[1:a]..` into `[out]`
+fn mix_filter(bgms: &[BgmTrack]) -> String {
+    let ducks = bgms.iter().filter(|bgm| bgm.duck_under_speech).count();
+    let mut filters = vec![];
+    // NOTE: the speech is split to be used as sidechains of ducked bgms
+    let sidechains = (0..ducks)
+        .map(|k| format!("[sc{}]", k))
+        .collect::<Vec<_>>()
+        .concat();
+    filters.push(format!("[0:a]asplit={}[speech]{}", ducks + 1, sidechains));
+
+    let mut mixed = vec!["[speech]".to_string()];
+    let mut sidechain = 0;
+    for (k, bgm) in bgms.iter().enumerate() {
+        let duration = bgm.duration.as_secs_f64();
+        let delay = bgm.start.as_millis();
+        let mut chain = vec![
+            format!("atrim=0:{:.3}", duration),
+            "asetpts=PTS-STARTPTS".to_string(),
+            "aformat=sample_rates=44100:channel_layouts=stereo".to_string(),
+            format!("volume={}", bgm.volume),
+        ];
+        if let Some(fade_in) = bgm.fade_in {
+            chain.push(format!("afade=t=in:st=0:d={}", fade_in));
+        }
+        if let Some(fade_out) = bgm.fade_out {
+            let start = (duration - fade_out).max(0.0);
+            chain.push(format!("afade=t=out:st={:.3}:d={}", start, fade_out));
+        }
+        chain.push(format!("adelay={}|{}", delay, delay));
+        filters.push(format!("[{}:a]{}[bgm{}]", k + 1, chain.join(","), k));
+        if bgm.duck_under_speech {
+            filters.push(format!(
+                "[bgm{}][sc{}]sidechaincompress=threshold=0.02:ratio=8:attack=20:release=400[duck{}]",
+                k, sidechain, k
+            ));
+            sidechain += 1;
+            mixed.push(format!("[duck{}]", k));
+        } else {
+            mixed.push(format!("[bgm{}]", k));
+        }
+    }
+    filters.push(format!(
+        "{}amix=inputs={}:duration=first:normalize=0[out]",
+        mixed.concat(),
+        mixed.len()
+    ));
+    filters.join(";")
+}
+
+/// mixes bgms into the speech
+#[instrument(skip(work_dir))]
+pub(crate) async fn mix_audios(
+    work_dir: &WorkDir,
+    speech: &Path,
+    bgms: &[BgmTrack],
+    cancel: &CancellationToken,
+) -> Result<PathBuf, AudioError> {
+    let mixed_path = work_dir.dir().join("mixed.wav");
+    let mut args = vec![
+        "-y".to_string(),
+        "-i".to_string(),
+        speech.display().to_string(),
+    ];
+    for bgm in bgms.iter() {
         args.extend([
-            "-ss".to_string(),
-            from_sec.to_string(),
-            "-to".to_string(),
-            (to_sec - from_sec).to_string(),
+            "-stream_loop".to_string(),
+            "-1".to_string(),
+            "-i".to_string(),
+            bgm.path.display().to_string(),
         ]);
     }
-    args.push(output.display().to_string());
-    cmd.args(args);
-    let res = cmd.output().await?;
-    if !res.status.success() {
-        anyhow::bail!("Failed to slice audio: {}", String::from_utf8(res.stderr)?);
+    if !bgms.is_empty() {
+        args.extend([
+            "-filter_complex".to_string(),
+            mix_filter(bgms),
+            "-map".to_string(),
+            "[out]".to_string(),
+        ]);
     }
-    Ok(())
+    args.extend(["-vn", "-ar", "44100", "-ac", "2"].map(ToString::to_string));
+    args.push(mixed_path.display().to_string());
+    ffmpeg("mix audios", args, cancel).await?;
+    Ok(mixed_path)
 }
 
-pub(crate) fn convert_to_stereo_wav(input: &Path, output: &Path) -> anyhow::Result<()> {
-    let mut cmd = std::process::Command::new("ffmpeg");
-    cmd.args([
-        "-i",
-        input.display().to_string().as_str(),
-        "-ac",
-        "2",
-        output.display().to_string().as_str(),
-    ]);
-    let res = cmd.output()?;
-    if !res.status.success() {
-        anyhow::bail!(
-            "Failed to convert to stereo wav: {}",
-            String::from_utf8(res.stderr)?
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let version = |line: &str| parse_version(line);
+        assert_eq!(
+            version("ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023"),
+            Some((6, 1))
         );
+        assert_eq!(version("ffmpeg version n4.4.2 Copyright"), Some((4, 4)));
+        assert_eq!(version("ffmpeg version 7.0 Copyright"), Some((7, 0)));
+        assert_eq!(version("ffmpeg version N-112345-gabcdef Copyright"), None);
     }
-    Ok(())
-}
 
-#[instrument(skip(work_dir, paths))]
-pub(crate) async fn concat_audios(
-    work_dir: &WorkDir,
-    paths: &[PathBuf],
-) -> anyhow::Result<PathBuf> {
-    let inputs_path = work_dir.dir().join("inputs.txt");
-    let text = paths
-        .iter()
-        .map(|p| format!("file '{}'", p.file_name().unwrap().to_string_lossy()))
-        .collect::<Vec<_>>()
-        .join("\n");
-    let mut f = OpenOptions::new()
-        .truncate(true)
-        .write(true)
-        .create(true)
-        .open(&inputs_path)?;
-    f.write_all(text.as_bytes())?;
-
-    let episode_audio_path = work_dir.dir().join("episode.mp3");
-    let mut cmd = Command::new("ffmpeg");
-    cmd.args([
-        "-y",
-        "-f",
-        "concat",
-        "-i",
-        inputs_path.display().to_string().as_str(),
-        "-vn",
-        "-ar",
-        "44100",
-        "-ac",
-        "2",
-        "-b:a",
-        "192k",
-        episode_audio_path.display().to_string().as_str(),
-    ]);
-    let res = cmd.output().await?;
-    if !res.status.success() {
-        anyhow::bail!(
-            "Failed to concat audios: {}",
-            String::from_utf8(res.stderr)?
+    #[test]
+    fn test_mix_filter() {
+        let bgm = BgmTrack {
+            path: PathBuf::from("bgm.mp3"),
+            start: Duration::from_secs(3),
+            duration: Duration::from_secs(60),
+            volume: 0.3,
+            fade_in: Some(2.0),
+            fade_out: Some(5.0),
+            duck_under_speech: true,
+        };
+        let jingle_bed = BgmTrack {
+            start: Duration::ZERO,
+            duration: Duration::from_secs(3),
+            fade_in: None,
+            fade_out: None,
+            duck_under_speech: false,
+            ..bgm.clone()
+        };
+        assert_eq!(
+            mix_filter(&[bgm, jingle_bed]),
+            [
+                "[0:a]asplit=2[speech][sc0]",
+                "[1:a]atrim=0:60.000,asetpts=PTS-STARTPTS,aformat=sample_rates=44100:channel_layouts=stereo,volume=0.3,afade=t=in:st=0:d=2,afade=t=out:st=55.000:d=5,adelay=3000|3000[bgm0]",
+                "[bgm0][sc0]sidechaincompress=threshold=0.02:ratio=8:attack=20:release=400[duck0]",
+                "[2:a]atrim=0:3.000,asetpts=PTS-STARTPTS,aformat=sample_rates=44100:channel_layouts=stereo,volume=0.3,adelay=0|0[bgm1]",
+                "[speech][duck0][bgm1]amix=inputs=3:duration=first:normalize=0[out]",
+            ]
+            .join(";")
         );
     }
-    Ok(episode_audio_path)
 }
@@ -1,72 +1,357 @@
 use crate::{
-    audio_downloader::AudioDownloader,
-    ffmpeg::{concat_audios, get_duration},
-    voicevox::client::VoiceVoxClient,
+    audio_downloader::{AudioDownloader, SourceConfig},
+    dictionary::Dictionary,
+    ffmpeg::{mix_audios, BgmTrack},
+    mastering::{master_audio, Loudness, MasteringConfig},
+    output::{encode, ffmetadata, Chapter, OutputConfig, Rendition},
+    pcm::{self, blocking, wav_duration, Pcm},
+    silence::SilenceGenerator,
+    transcript::{Mora, Transcript, TranscriptSection, TranscriptSentence},
+    tts::{DictionaryTts, TtsRegistry},
     workdir::WorkDir,
     AudioGenerator,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use api::episode::Section;
-use srtlib::{Subtitle, Subtitles, Timestamp};
-use std::{fs::File, path::PathBuf, time::Duration};
-use wavers::Wav;
+use futures::{stream, StreamExt};
+use std::{ops::Range, time::Duration};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
-fn resolve_audio_generator(section: &Section) -> Result<Box<dyn AudioGenerator>> {
-    match section {
-        Section::Serif { .. } => {
-            let end_point = std::env::var("VOICEVOX_ENDPOINT")?;
-            Ok(Box::new(VoiceVoxClient::new(end_point)))
+/// generators shared by all sections of an episode
+struct AudioGenerators<'a> {
+    tts: DictionaryTts<'a>,
+    downloader: AudioDownloader,
+    silence: SilenceGenerator,
+}
+
+impl<'a> AudioGenerators<'a> {
+    fn load(tts: &'a TtsRegistry, dictionary: &'a Dictionary) -> Result<Self> {
+        Ok(Self {
+            tts: tts.with_dictionary(dictionary),
+            downloader: AudioDownloader::new(SourceConfig::from_env()?),
+            silence: SilenceGenerator,
+        })
+    }
+
+    /// `None` for sections without clips of their own
+    fn resolve(&self, section: &Section) -> Option<&dyn AudioGenerator> {
+        match section {
+            Section::Serif { .. } => Some(&self.tts),
+            Section::Audio { .. } | Section::Jingle { .. } => Some(&self.downloader),
+            Section::Silence { .. } => Some(&self.silence),
+            Section::Chapter { .. } | Section::Bgm { .. } => None,
         }
-        Section::Audio { .. } => Ok(Box::new(AudioDownloader::new())),
     }
 }
 
+/// sections played over by each bgm, as `(index of the bgm, range of sections)`
+fn bgm_ranges(sections: &[Section]) -> Vec<(usize, Range<usize>)> {
+    let bgms = sections
+        .iter()
+        .enumerate()
+        .filter(|(_, section)| matches!(section, Section::Bgm { .. }))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    bgms.iter()
+        .enumerate()
+        .map(|(k, &i)| {
+            let Section::Bgm { sections: span, .. } = &sections[i] else {
+                unreachable!("filtered bgms");
+            };
+            let end = match span {
+                Some(span) => (i + 1 + span).min(sections.len()),
+                None => bgms.get(k + 1).copied().unwrap_or(sections.len()),
+            };
+            (i, i + 1..end)
+        })
+        .collect()
+}
+
+/// `type` of the section
+fn kind(section: &Section) -> &'static str {
+    match section {
+        Section::Serif { .. } => "Serif",
+        Section::Audio { .. } => "Audio",
+        Section::Silence { .. } => "Silence",
+        Section::Jingle { .. } => "Jingle",
+        Section::Chapter { .. } => "Chapter",
+        Section::Bgm { .. } => "Bgm",
+    }
+}
+
+/// label of the voice in subtitles
+fn speaker(section: &Section) -> Option<String> {
+    match section {
+        Section::Serif {
+            speaker,
+            speaker_name,
+            ..
+        } => Some(speaker_name.clone().unwrap_or(speaker.clone())),
+        _ => None,
+    }
+}
+
+/// chapters from each `Section::Chapter` to the next one or the end
+fn chapters(sections: &[Section], section_starts: &[Duration]) -> Vec<Chapter> {
+    let marks = sections
+        .iter()
+        .enumerate()
+        .filter_map(|(i, section)| match section {
+            Section::Chapter { title } => Some((i, title)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    marks
+        .iter()
+        .enumerate()
+        .map(|(k, (i, title))| {
+            let end = marks.get(k + 1).map(|(j, _)| *j).unwrap_or(sections.len());
+            Chapter {
+                title: title.to_string(),
+                start_sec: section_starts[*i].as_secs_f64(),
+                end_sec: section_starts[end].as_secs_f64(),
+            }
+        })
+        .collect()
+}
+
 pub struct SynthesisResult {
-    pub out_path: PathBuf,
-    pub srt: String,
-    pub duration_sec: f64,
+    /// in the order of `OutputConfig::profiles`
+    pub renditions: Vec<Rendition>,
+    pub chapters: Vec<Chapter>,
+    pub transcript: Transcript,
+    /// measured after mastering
+    pub loudness: Loudness,
 }
 
+/// sections are generated concurrently, keeping their order.
+/// serifs are read by `tts` with `dictionary` applied, while subtitles keep the original text.
+/// the episode is mastered by `MasteringConfig::from_env` and encoded per `output` profiles.
+/// stops between sections and sentences when `cancel` is triggered
 pub async fn generate_audio(
     work_dir: &WorkDir,
     sections: Vec<Section>,
+    tts: &TtsRegistry,
+    dictionary: &Dictionary,
+    output: &OutputConfig,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<SynthesisResult> {
-    let mut subs = vec![];
-
-    let mut i = 0;
-    for section in sections {
-        let generator = resolve_audio_generator(&section)?;
-        let paths = generator
-            .generate(&mut i, work_dir, section.clone())
-            .await?;
-        subs.extend(paths);
+    if output.profiles.is_empty() {
+        anyhow::bail!("No output profiles");
     }
+    let generators = AudioGenerators::load(tts, dictionary)?;
+    let mastering = MasteringConfig::from_env()?;
+    let n_sections = sections.len();
+    let mut clips = stream::iter(sections.iter().cloned().enumerate())
+        .map(|(n, section)| {
+            let generators = &generators;
+            async move {
+                if cancel.is_cancelled() {
+                    anyhow::bail!("Cancelled");
+                }
+                let Some(generator) = generators.resolve(&section) else {
+                    return Ok(vec![]);
+                };
+                let gain = section.gain();
+                let mut clips = generator
+                    .generate(work_dir, section, cancel)
+                    .await
+                    .with_context(|| format!("section {}/{}", n + 1, n_sections))?;
+                if let Some(gain) = gain {
+                    for clip in clips.iter_mut() {
+                        // NOTE: clips of the same sentence share a file
+                        let input = clip.path.clone();
+                        clip.path = input.with_file_name(format!("{}.wav", Uuid::new_v4()));
+                        let output = clip.path.clone();
+                        blocking(move || Pcm::read(&input)?.amplify(gain).write(&output)).await?;
+                    }
+                }
+                Ok(clips)
+            }
+        })
+        .buffered(tts.concurrency);
 
-    let mut srt = Subtitles::new();
+    let mut section_clips = vec![];
+    let mut n_clips = 0;
+    while let Some(paths) = clips.next().await {
+        match paths {
+            Ok(paths) => {
+                n_clips += paths.len();
+                section_clips.push(paths);
+            }
+            Err(_) if cancel.is_cancelled() => anyhow::bail!(
+                "Cancelled after {}/{} sections, {} sentences",
+                section_clips.len(),
+                n_sections,
+                n_clips
+            ),
+            Err(e) => return Err(e),
+        }
+    }
+    drop(clips);
+
+    let mut transcript = Transcript::default();
     let mut duration = Duration::ZERO;
+    // NOTE: section_starts[n_sections] is the end of the episode
+    let mut section_starts = Vec::with_capacity(n_sections + 1);
 
     let mmss = |d: &Duration| format!("{:02}:{:02}", d.as_secs() / 60, d.as_secs() % 60);
-    for (i, (path, sentence)) in subs.iter().enumerate() {
-        let file = Box::new(File::open(path)?);
-        let r = Wav::<i16>::new(file)?;
-        let (start, end) = (duration, duration + get_duration(&r));
-        tracing::info!("{} -> {}: {}", mmss(&start), mmss(&end), sentence);
-        let sub = Subtitle::new(
-            i,
-            Timestamp::from_milliseconds(start.as_millis() as u32),
-            Timestamp::from_milliseconds(end.as_millis() as u32),
-            sentence.to_string(),
-        );
-        srt.push(sub);
-        duration = end;
+    for (section, clips) in sections.iter().zip(section_clips.iter()) {
+        section_starts.push(duration);
+        let mut sentences = vec![];
+        for clip in clips.iter() {
+            let (start, end) = (duration, duration + wav_duration(&clip.path)?);
+            duration = end;
+            // NOTE: clips without text such as silences only take time
+            if clip.text.is_empty() {
+                continue;
+            }
+            tracing::info!("{} -> {}: {}", mmss(&start), mmss(&end), clip.text);
+            let offset = start.as_secs_f64();
+            sentences.push(TranscriptSentence {
+                text: clip.text.clone(),
+                start_sec: offset,
+                end_sec: end.as_secs_f64(),
+                moras: clip
+                    .moras
+                    .iter()
+                    .map(|mora| Mora {
+                        text: mora.text.clone(),
+                        start_sec: offset + mora.start_sec,
+                        end_sec: offset + mora.end_sec,
+                    })
+                    .collect(),
+            });
+        }
+        transcript.sections.push(TranscriptSection {
+            kind: kind(section).to_string(),
+            speaker: speaker(section),
+            start_sec: section_starts.last().expect("pushed").as_secs_f64(),
+            end_sec: duration.as_secs_f64(),
+            sentences,
+        });
     }
+    section_starts.push(duration);
+    transcript.duration_sec = duration.as_secs_f64();
 
-    let paths = subs.into_iter().map(|(path, _)| path).collect::<Vec<_>>();
-    let episode_audio_path = concat_audios(work_dir, &paths).await?;
+    let mut bgms = vec![];
+    for (i, range) in bgm_ranges(&sections) {
+        let Section::Bgm {
+            url,
+            volume,
+            fade_in,
+            fade_out,
+            duck_under_speech,
+            ..
+        } = &sections[i]
+        else {
+            unreachable!("bgm_ranges returns bgms");
+        };
+        let (start, end) = (section_starts[range.start], section_starts[range.end]);
+        if start >= end {
+            continue;
+        }
+        bgms.push(BgmTrack {
+            path: generators
+                .downloader
+                .download_audio(work_dir, url, cancel)
+                .await?,
+            start,
+            duration: end - start,
+            volume: *volume,
+            fade_in: *fade_in,
+            fade_out: *fade_out,
+            duck_under_speech: *duck_under_speech,
+        });
+    }
+
+    let paths = section_clips
+        .into_iter()
+        .flatten()
+        .map(|clip| clip.path)
+        .collect::<Vec<_>>();
+    let speech_path = work_dir.dir().join("speech.wav");
+    let speech = speech_path.clone();
+    blocking(move || pcm::concat(&paths, &speech)).await?;
+    let mixed_path = mix_audios(work_dir, &speech_path, &bgms, cancel).await?;
+    let (mastered_path, loudness) = master_audio(work_dir, &mixed_path, &mastering, cancel).await?;
+
+    let chapters = chapters(&sections, &section_starts);
+    let metadata_path = work_dir.dir().join("metadata.txt");
+    tokio::fs::write(&metadata_path, ffmetadata(&output.tags, &chapters)).await?;
+    // NOTE: a broken artwork should not fail the episode
+    let artwork = match output.tags.artwork_url.as_ref() {
+        Some(url) => generators
+            .downloader
+            .download(work_dir, url, "img", cancel)
+            .await
+            .inspect_err(|e| tracing::warn!("Failed to download artwork: {:?}", e))
+            .ok(),
+        None => None,
+    };
+    let mut renditions = vec![];
+    for profile in output.profiles.iter() {
+        let path = encode(
+            work_dir,
+            &mastered_path,
+            profile,
+            &metadata_path,
+            artwork.as_deref(),
+            cancel,
+        )
+        .await?;
+        renditions.push(Rendition {
+            profile: profile.clone(),
+            path,
+        });
+    }
 
     Ok(SynthesisResult {
-        out_path: episode_audio_path,
-        srt: srt.to_string(),
-        duration_sec: duration.as_secs_f64(),
+        renditions,
+        chapters,
+        transcript,
+        loudness,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_bgm_ranges() -> Result<()> {
+        let serif = json!({ "type": "Serif", "speaker": "1", "text": "こんにちは" });
+        let bgm = |sections: Option<usize>| json!({ "type": "Bgm", "url": "https://example.com/bgm.mp3", "sections": sections });
+        let sections: Vec<Section> =
+            serde_json::from_value(json!(
+                [bgm(None), serif, serif, bgm(Some(1)), serif, serif,]
+            ))?;
+        assert_eq!(bgm_ranges(&sections), vec![(0, 1..3), (3, 4..5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chapters() -> Result<()> {
+        let serif = json!({ "type": "Serif", "speaker": "1", "text": "こんにちは" });
+        let sections: Vec<Section> = serde_json::from_value(json!([
+            { "type": "Chapter", "title": "opening" },
+            serif,
+            { "type": "Chapter", "title": "news" },
+            serif,
+        ]))?;
+        let starts = [0, 0, 10, 10, 25].map(Duration::from_secs);
+        assert_eq!(
+            chapters(&sections, &starts)
+                .into_iter()
+                .map(|chapter| (chapter.title, chapter.start_sec, chapter.end_sec))
+                .collect::<Vec<_>>(),
+            vec![
+                ("opening".to_string(), 0.0, 10.0),
+                ("news".to_string(), 10.0, 25.0)
+            ]
+        );
+        Ok(())
+    }
+}
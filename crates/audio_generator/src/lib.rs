@@ -1,21 +1,65 @@
+pub mod dictionary;
+pub mod error;
 pub mod ffmpeg;
 pub mod generate_audio;
+pub mod mastering;
+pub mod output;
+pub mod pcm;
+pub mod transcript;
+pub mod tts;
 pub mod voicevox;
 pub mod workdir;
 
 mod audio_downloader;
+mod cache;
+mod silence;
 
 use api::episode::Section;
 use async_trait::async_trait;
 use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
+use transcript::Mora;
 use workdir::WorkDir;
 
+/// an audio file written by an `AudioGenerator`
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub path: PathBuf,
+    /// subtitle, empty if none
+    pub text: String,
+    /// from the start of the clip, empty if unknown
+    pub moras: Vec<Mora>,
+}
+
+impl Clip {
+    pub fn new(path: PathBuf, text: String) -> Self {
+        Self {
+            path,
+            text,
+            moras: vec![],
+        }
+    }
+}
+
+pub(crate) fn env_or<T: std::str::FromStr>(key: &str, default: T) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| anyhow::Error::new(e).context(format!("Invalid {}", key))),
+        Err(_) => Ok(default),
+    }
+}
+
 #[async_trait]
 pub trait AudioGenerator: Send + Sync {
+    /// writes clips of the section into the work dir with unique names, in playback order.
     async fn generate(
         &self,
-        i: &mut usize,
         workdir: &WorkDir,
         section: Section,
-    ) -> anyhow::Result<Vec<(PathBuf, String)>>;
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<Clip>>;
 }
@@ -0,0 +1,176 @@
+use crate::{env_or, ffmpeg::ffmpeg, workdir::WorkDir};
+use anyhow::Context;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+
+/// targets of the mastering pass, EBU R128 for podcasts by default
+#[derive(Debug, Clone)]
+pub struct MasteringConfig {
+    /// integrated loudness in LUFS
+    pub target_lufs: f64,
+    /// true peak limit in dBTP
+    pub true_peak: f64,
+    /// loudness range in LU
+    pub lra: f64,
+    /// cutoff of the high-pass filter in Hz
+    pub highpass_hz: Option<f64>,
+}
+
+impl Default for MasteringConfig {
+    fn default() -> Self {
+        Self {
+            target_lufs: -16.0,
+            true_peak: -1.5,
+            lra: 11.0,
+            highpass_hz: None,
+        }
+    }
+}
+
+impl MasteringConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+        Ok(Self {
+            target_lufs: env_or("MASTERING_TARGET_LUFS", default.target_lufs)?,
+            true_peak: env_or("MASTERING_TRUE_PEAK_DBTP", default.true_peak)?,
+            lra: env_or("MASTERING_LRA", default.lra)?,
+            highpass_hz: std::env::var("MASTERING_HIGHPASS_HZ")
+                .ok()
+                .map(|hz| hz.parse())
+                .transpose()
+                .context("Invalid MASTERING_HIGHPASS_HZ")?,
+        })
+    }
+
+    fn loudnorm(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}",
+            self.target_lufs, self.true_peak, self.lra
+        )
+    }
+
+    fn filters(&self, loudnorm: String) -> String {
+        match self.highpass_hz {
+            Some(hz) => format!("highpass=f={},{}", hz, loudnorm),
+            None => loudnorm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Loudness {
+    /// integrated loudness in LUFS
+    pub integrated: f64,
+    /// in dBTP
+    pub true_peak: f64,
+    /// loudness range in LU
+    pub lra: f64,
+}
+
+/// the json printed by `loudnorm=print_format=json` at the end of stderr
+fn parse_loudnorm(stderr: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    let start = stderr.rfind('{').context("No loudnorm stats")?;
+    let end = stderr[start..].find('}').context("No loudnorm stats")?;
+    Ok(serde_json::from_str(&stderr[start..=start + end])?)
+}
+
+fn stat(stats: &BTreeMap<String, String>, key: &str) -> anyhow::Result<f64> {
+    let value = stats.get(key).with_context(|| format!("No {}", key))?;
+    value
+        .parse()
+        .with_context(|| format!("Invalid {}: {}", key, value))
+}
+
+/// normalizes loudness in two passes into a wav,
+/// returns the loudness measured on the output
+#[instrument(skip(work_dir, cancel))]
+pub(crate) async fn master_audio(
+    work_dir: &WorkDir,
+    input: &Path,
+    config: &MasteringConfig,
+    cancel: &CancellationToken,
+) -> anyhow::Result<(PathBuf, Loudness)> {
+    let input = input.display().to_string();
+    let measure = config.filters(format!("{}:print_format=json", config.loudnorm()));
+    let res = ffmpeg(
+        "measure loudness",
+        ["-i", &input, "-af", &measure, "-f", "null", "-"],
+        cancel,
+    )
+    .await?;
+    let stderr = String::from_utf8(res.stderr)?;
+    let measured = parse_loudnorm(&stderr)?;
+    tracing::info!("measured: {:?}", measured);
+
+    let normalize = config.filters(format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true:print_format=json",
+        config.loudnorm(),
+        stat(&measured, "input_i")?,
+        stat(&measured, "input_tp")?,
+        stat(&measured, "input_lra")?,
+        stat(&measured, "input_thresh")?,
+        stat(&measured, "target_offset")?,
+    ));
+    let mastered_path = work_dir.dir().join("mastered.wav");
+    let res = ffmpeg(
+        "normalize loudness",
+        [
+            "-y",
+            "-i",
+            &input,
+            "-af",
+            &normalize,
+            "-vn",
+            // NOTE: loudnorm upsamples to 192kHz
+            "-ar",
+            "44100",
+            "-ac",
+            "2",
+            mastered_path.display().to_string().as_str(),
+        ],
+        cancel,
+    )
+    .await?;
+    let stderr = String::from_utf8(res.stderr)?;
+    let normalized = parse_loudnorm(&stderr)?;
+    let loudness = Loudness {
+        integrated: stat(&normalized, "output_i")?,
+        true_peak: stat(&normalized, "output_tp")?,
+        lra: stat(&normalized, "output_lra")?,
+    };
+    Ok((mastered_path, loudness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_loudnorm() -> anyhow::Result<()> {
+        let stderr = r#"size=N/A time=00:00:10.00 bitrate=N/A speed= 500x
+[Parsed_loudnorm_0 @ 0x7f8b8c004a80]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-16.58",
+	"output_tp" : "-1.50",
+	"output_lra" : "14.78",
+	"output_thresh" : "-27.71",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.58"
+}
+"#;
+        let stats = parse_loudnorm(stderr)?;
+        assert_eq!(stat(&stats, "input_i")?, -27.61);
+        assert_eq!(stat(&stats, "target_offset")?, 0.58);
+        assert!(stat(&stats, "normalization_type").is_err());
+        Ok(())
+    }
+}
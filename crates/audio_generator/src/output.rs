@@ -0,0 +1,279 @@
+use crate::{ffmpeg::ffmpeg, workdir::WorkDir};
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    /// AAC in MP4
+    M4a,
+    /// Opus in Ogg
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Opus => "opus",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::M4a => "audio/mp4",
+            AudioFormat::Opus => "audio/ogg",
+        }
+    }
+
+    fn codec(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "libmp3lame",
+            AudioFormat::M4a => "aac",
+            AudioFormat::Opus => "libopus",
+        }
+    }
+
+    /// opus supports only 48kHz and below
+    fn sample_rate(&self) -> u32 {
+        match self {
+            AudioFormat::Opus => 48000,
+            AudioFormat::Mp3 | AudioFormat::M4a => 44100,
+        }
+    }
+
+    /// whether artwork can be embedded as a cover picture
+    fn embeds_artwork(&self) -> bool {
+        matches!(self, AudioFormat::Mp3 | AudioFormat::M4a)
+    }
+}
+
+/// an encoding of episodes
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputProfile {
+    pub format: AudioFormat,
+    #[serde(default = "OutputProfile::default_bitrate_kbps")]
+    pub bitrate_kbps: u32,
+    #[serde(default = "OutputProfile::default_channels")]
+    pub channels: u8,
+}
+
+impl Default for OutputProfile {
+    fn default() -> Self {
+        Self {
+            format: AudioFormat::Mp3,
+            bitrate_kbps: Self::default_bitrate_kbps(),
+            channels: Self::default_channels(),
+        }
+    }
+}
+
+impl OutputProfile {
+    fn default_bitrate_kbps() -> u32 {
+        192
+    }
+
+    fn default_channels() -> u8 {
+        2
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(16..=320).contains(&self.bitrate_kbps) {
+            anyhow::bail!("bitrateKbps must be in 16..=320: {}", self.bitrate_kbps);
+        }
+        if !(1..=2).contains(&self.channels) {
+            anyhow::bail!("channels must be 1 or 2: {}", self.channels);
+        }
+        Ok(())
+    }
+
+    /// e.g. `mp3-192k-2ch`
+    pub fn name(&self) -> String {
+        format!(
+            "{}-{}k-{}ch",
+            self.format.extension(),
+            self.bitrate_kbps,
+            self.channels
+        )
+    }
+}
+
+/// tags embedded as ID3 / MP4 / Vorbis comments
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+    pub title: String,
+    /// podcast title
+    pub album: Option<String>,
+    pub artwork_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Chapter {
+    pub title: String,
+    pub start_sec: f64,
+    pub end_sec: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OutputConfig {
+    /// the first one is the primary audio
+    pub profiles: Vec<OutputProfile>,
+    pub tags: Tags,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    pub profile: OutputProfile,
+    pub path: PathBuf,
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// the FFMETADATA1 file of tags and chapters
+pub(crate) fn ffmetadata(tags: &Tags, chapters: &[Chapter]) -> String {
+    let mut lines = vec![";FFMETADATA1".to_string()];
+    lines.push(format!("title={}", escape(&tags.title)));
+    if let Some(album) = tags.album.as_ref() {
+        lines.push(format!("album={}", escape(album)));
+        lines.push(format!("artist={}", escape(album)));
+    }
+    lines.push("genre=Podcast".to_string());
+    for chapter in chapters.iter() {
+        lines.extend([
+            "[CHAPTER]".to_string(),
+            "TIMEBASE=1/1000".to_string(),
+            format!("START={}", (chapter.start_sec * 1000.0).round() as u64),
+            format!("END={}", (chapter.end_sec * 1000.0).round() as u64),
+            format!("title={}", escape(&chapter.title)),
+        ]);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// encodes the mastered audio with tags and chapters in `metadata`
+#[instrument(skip(work_dir, cancel))]
+pub(crate) async fn encode(
+    work_dir: &WorkDir,
+    input: &Path,
+    profile: &OutputProfile,
+    metadata: &Path,
+    artwork: Option<&Path>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<PathBuf> {
+    let output = work_dir.dir().join(format!(
+        "episode.{}.{}",
+        profile.name(),
+        profile.format.extension()
+    ));
+    let mut args = vec![
+        "-y".to_string(),
+        "-i".to_string(),
+        input.display().to_string(),
+        "-i".to_string(),
+        metadata.display().to_string(),
+    ];
+    let artwork = artwork.filter(|_| profile.format.embeds_artwork());
+    if let Some(artwork) = artwork {
+        args.extend(["-i".to_string(), artwork.display().to_string()]);
+    }
+    args.extend(
+        ["-map", "0:a", "-map_metadata", "1", "-map_chapters", "1"].map(ToString::to_string),
+    );
+    if artwork.is_some() {
+        args.extend(
+            [
+                "-map",
+                "2:v",
+                "-c:v",
+                "copy",
+                "-disposition:v",
+                "attached_pic",
+            ]
+            .map(ToString::to_string),
+        );
+    }
+    if profile.format == AudioFormat::Mp3 {
+        args.extend(["-id3v2_version", "3"].map(ToString::to_string));
+    }
+    args.extend([
+        "-c:a".to_string(),
+        profile.format.codec().to_string(),
+        "-b:a".to_string(),
+        format!("{}k", profile.bitrate_kbps),
+        "-ac".to_string(),
+        profile.channels.to_string(),
+        "-ar".to_string(),
+        profile.format.sample_rate().to_string(),
+        output.display().to_string(),
+    ]);
+    ffmpeg("encode", args, cancel)
+        .await
+        .with_context(|| format!("Failed to encode {}", profile.name()))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_profile() -> anyhow::Result<()> {
+        let profile: OutputProfile = serde_json::from_value(json!({ "format": "opus" }))?;
+        assert_eq!(profile.name(), "opus-192k-2ch");
+        assert_eq!(profile.format.mime_type(), "audio/ogg");
+        let mono: OutputProfile =
+            serde_json::from_value(json!({ "format": "m4a", "bitrateKbps": 64, "channels": 1 }))?;
+        mono.validate()?;
+        assert_eq!(mono.name(), "m4a-64k-1ch");
+        assert_eq!(OutputProfile::default().format.mime_type(), "audio/mpeg");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ffmetadata() {
+        let tags = Tags {
+            title: "#1 A=B".to_string(),
+            album: Some("podcast".to_string()),
+            artwork_url: None,
+        };
+        let chapters = [Chapter {
+            title: "opening".to_string(),
+            start_sec: 0.0,
+            end_sec: 12.3456,
+        }];
+        assert_eq!(
+            ffmetadata(&tags, &chapters),
+            [
+                ";FFMETADATA1",
+                "title=\\#1 A\\=B",
+                "album=podcast",
+                "artist=podcast",
+                "genre=Podcast",
+                "[CHAPTER]",
+                "TIMEBASE=1/1000",
+                "START=0",
+                "END=12346",
+                "title=opening",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
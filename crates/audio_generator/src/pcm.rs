@@ -0,0 +1,373 @@
+use crate::error::AudioError;
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use wavers::Wav;
+
+/// format of the speech, clips are conformed to it before concatenation
+pub const SAMPLE_RATE: u32 = 44100;
+pub const CHANNELS: u16 = 2;
+
+/// the canonical 44 bytes header of 16-bit PCM
+pub(crate) fn wav_header(sample_rate: u32, n_channels: u16, data_len: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // PCM
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&n_channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * n_channels as u32 * 2).to_le_bytes());
+    header.extend_from_slice(&(n_channels * 2).to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+/// whether the file starts with a RIFF WAVE header
+pub(crate) fn is_wav(head: &[u8]) -> bool {
+    head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WAVE"
+}
+
+fn wav_error(path: &Path) -> impl FnOnce(wavers::WaversError) -> AudioError {
+    let path = path.to_path_buf();
+    move |source| AudioError::Wav { path, source }
+}
+
+/// runs blocking audio work off the async runtime
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T, AudioError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, AudioError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// duration from the header without reading samples
+pub fn wav_duration(path: &Path) -> Result<Duration, AudioError> {
+    let wav = Wav::<i16>::from_path(path).map_err(wav_error(path))?;
+    let data_size = wav.header().data().size as f64;
+    let fmt = wav.header().fmt_chunk;
+    let bytes_per_sec =
+        fmt.sample_rate as f64 * fmt.channels as f64 * (fmt.bits_per_sample / 8) as f64;
+    Ok(Duration::from_secs_f64(data_size / bytes_per_sec))
+}
+
+/// interleaved 16-bit samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub n_channels: u16,
+    pub samples: Vec<i16>,
+}
+
+impl Pcm {
+    /// samples of other bit depths are converted to 16-bit
+    pub fn read(path: &Path) -> Result<Self, AudioError> {
+        let mut wav = Wav::<i16>::from_path(path).map_err(wav_error(path))?;
+        let samples = wav.read().map_err(wav_error(path))?;
+        Ok(Self {
+            sample_rate: wav.sample_rate() as u32,
+            n_channels: wav.n_channels(),
+            samples: samples.to_vec(),
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), AudioError> {
+        let mut writer = WavWriter::create(path, self.sample_rate, self.n_channels)?;
+        writer.append(self)?;
+        writer.finish()
+    }
+
+    pub fn to_wav_bytes(&self) -> Vec<u8> {
+        let mut wav = wav_header(
+            self.sample_rate,
+            self.n_channels,
+            (self.samples.len() * 2) as u32,
+        );
+        wav.reserve(self.samples.len() * 2);
+        for sample in self.samples.iter() {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    pub fn silence(duration: Duration, sample_rate: u32, n_channels: u16) -> Self {
+        let n_frames = (duration.as_secs_f64() * sample_rate as f64).round() as usize;
+        Self {
+            sample_rate,
+            n_channels,
+            samples: vec![0; n_frames * n_channels as usize],
+        }
+    }
+
+    pub fn n_frames(&self) -> usize {
+        self.samples.len() / self.n_channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.n_frames() as f64 / self.sample_rate as f64)
+    }
+
+    fn frame(&self, i: usize) -> &[i16] {
+        let n = self.n_channels as usize;
+        &self.samples[i * n..(i + 1) * n]
+    }
+
+    /// downmixes to mono by averaging, upmixes mono by copying
+    pub fn with_channels(self, n_channels: u16) -> Self {
+        if self.n_channels == n_channels {
+            return self;
+        }
+        let mut samples = Vec::with_capacity(self.n_frames() * n_channels as usize);
+        for i in 0..self.n_frames() {
+            let frame = self.frame(i);
+            if n_channels == 1 {
+                let sum = frame.iter().map(|&s| s as i32).sum::<i32>();
+                samples.push((sum / frame.len() as i32) as i16);
+            } else {
+                for c in 0..n_channels as usize {
+                    samples.push(frame[c.min(frame.len() - 1)]);
+                }
+            }
+        }
+        Self {
+            sample_rate: self.sample_rate,
+            n_channels,
+            samples,
+        }
+    }
+
+    /// linear interpolation, good enough for speech
+    pub fn resample(self, sample_rate: u32) -> Self {
+        if self.sample_rate == sample_rate || self.samples.is_empty() {
+            return Self {
+                sample_rate,
+                ..self
+            };
+        }
+        let ratio = self.sample_rate as f64 / sample_rate as f64;
+        let n_frames = (self.n_frames() as f64 / ratio).round() as usize;
+        let last = self.n_frames() - 1;
+        let mut samples = Vec::with_capacity(n_frames * self.n_channels as usize);
+        for i in 0..n_frames {
+            let pos = i as f64 * ratio;
+            let i0 = (pos.floor() as usize).min(last);
+            let i1 = (i0 + 1).min(last);
+            let frac = pos - i0 as f64;
+            for (&a, &b) in self.frame(i0).iter().zip(self.frame(i1).iter()) {
+                samples.push((a as f64 + (b as f64 - a as f64) * frac).round() as i16);
+            }
+        }
+        Self {
+            sample_rate,
+            n_channels: self.n_channels,
+            samples,
+        }
+    }
+
+    pub fn conform(self, sample_rate: u32, n_channels: u16) -> Self {
+        self.with_channels(n_channels).resample(sample_rate)
+    }
+
+    /// clips instead of wrapping around
+    pub fn amplify(self, gain_db: f64) -> Self {
+        let factor = 10f64.powf(gain_db / 20.0);
+        let samples = self
+            .samples
+            .into_iter()
+            .map(|s| {
+                (s as f64 * factor)
+                    .round()
+                    .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+            })
+            .collect();
+        Self { samples, ..self }
+    }
+
+    /// linear fades in seconds at the start and the end
+    pub fn fade(mut self, fade_in: f64, fade_out: f64) -> Self {
+        let n = self.n_channels as usize;
+        let n_frames = self.n_frames();
+        let sample_rate = self.sample_rate as f64;
+        let frames = |sec: f64| ((sec * sample_rate).round() as usize).min(n_frames);
+        let (fade_in, fade_out) = (frames(fade_in), frames(fade_out));
+        for i in 0..n_frames {
+            let mut gain = 1.0;
+            if i < fade_in {
+                gain *= i as f64 / fade_in as f64;
+            }
+            let rest = n_frames - 1 - i;
+            if rest < fade_out {
+                gain *= rest as f64 / fade_out as f64;
+            }
+            if gain < 1.0 {
+                for sample in self.samples[i * n..(i + 1) * n].iter_mut() {
+                    *sample = (*sample as f64 * gain).round() as i16;
+                }
+            }
+        }
+        self
+    }
+
+    /// keeps `from..to` in seconds, `to` beyond the end is clamped
+    pub fn slice(self, from: Option<f64>, to: Option<f64>) -> Result<Self, AudioError> {
+        let duration = self.duration();
+        let invalid = || AudioError::InvalidRange { from, to, duration };
+        let start = from.unwrap_or(0.0);
+        let end = to.unwrap_or(f64::INFINITY).min(duration.as_secs_f64());
+        if start < 0.0 || start >= end {
+            return Err(invalid());
+        }
+        let frame = |sec: f64| (sec * self.sample_rate as f64).round() as usize;
+        let n = self.n_channels as usize;
+        let (start, end) = (frame(start) * n, (frame(end) * n).min(self.samples.len()));
+        Ok(Self {
+            samples: self.samples[start..end].to_vec(),
+            ..self
+        })
+    }
+}
+
+/// writes samples incrementally and fills sizes in the header at last
+pub struct WavWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    sample_rate: u32,
+    n_channels: u16,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, n_channels: u16) -> Result<Self, AudioError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&wav_header(sample_rate, n_channels, 0))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            sample_rate,
+            n_channels,
+            data_len: 0,
+        })
+    }
+
+    /// conforms `pcm` to the format of the file
+    pub fn append(&mut self, pcm: &Pcm) -> Result<(), AudioError> {
+        let conformed;
+        let pcm = if pcm.sample_rate == self.sample_rate && pcm.n_channels == self.n_channels {
+            pcm
+        } else {
+            conformed = pcm.clone().conform(self.sample_rate, self.n_channels);
+            &conformed
+        };
+        for sample in pcm.samples.iter() {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += (pcm.samples.len() * 2) as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), AudioError> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&wav_header(
+            self.sample_rate,
+            self.n_channels,
+            self.data_len,
+        ))?;
+        self.file.flush()?;
+        tracing::debug!("{}: {} bytes", self.path.display(), self.data_len);
+        Ok(())
+    }
+}
+
+/// concats clips into a wav of `SAMPLE_RATE` and `CHANNELS`, reading one clip at a time
+pub fn concat(paths: &[PathBuf], output: &Path) -> Result<(), AudioError> {
+    let mut writer = WavWriter::create(output, SAMPLE_RATE, CHANNELS)?;
+    for path in paths.iter() {
+        writer.append(&Pcm::read(path)?)?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm(sample_rate: u32, n_channels: u16, samples: &[i16]) -> Pcm {
+        Pcm {
+            sample_rate,
+            n_channels,
+            samples: samples.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(
+            pcm(8000, 1, &[1, 2]).with_channels(2),
+            pcm(8000, 2, &[1, 1, 2, 2])
+        );
+        assert_eq!(
+            pcm(8000, 2, &[1, 3, -2, -4]).with_channels(1),
+            pcm(8000, 1, &[2, -3])
+        );
+    }
+
+    #[test]
+    fn test_resample() {
+        let up = pcm(2, 1, &[0, 100]).resample(4);
+        assert_eq!(up, pcm(4, 1, &[0, 50, 100, 100]));
+        let down = pcm(4, 2, &[0, 0, 10, -10, 20, -20, 30, -30]).resample(2);
+        assert_eq!(down, pcm(2, 2, &[0, 0, 20, -20]));
+        assert_eq!(pcm(24000, 1, &[0; 240]).resample(44100).n_frames(), 441);
+    }
+
+    #[test]
+    fn test_amplify() {
+        assert_eq!(
+            pcm(8000, 1, &[100, -100, 30000]).amplify(6.0206),
+            pcm(8000, 1, &[200, -200, i16::MAX])
+        );
+    }
+
+    #[test]
+    fn test_slice() -> anyhow::Result<()> {
+        let samples = (0..20).collect::<Vec<_>>();
+        let audio = pcm(10, 1, &samples);
+        assert_eq!(
+            audio.clone().slice(Some(0.5), Some(1.2))?.samples,
+            (5..12).collect::<Vec<_>>()
+        );
+        assert_eq!(audio.clone().slice(Some(1.5), None)?.samples.len(), 5);
+        assert_eq!(audio.clone().slice(None, Some(5.0))?.samples.len(), 20);
+        assert!(audio.clone().slice(Some(1.0), Some(0.5)).is_err());
+        assert!(audio.slice(Some(3.0), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fade() {
+        assert_eq!(
+            pcm(10, 1, &[100; 10]).fade(0.4, 0.2).samples,
+            vec![0, 25, 50, 75, 100, 100, 100, 100, 50, 0]
+        );
+        assert_eq!(pcm(10, 2, &[100; 4]).fade(0.0, 0.0).samples, vec![100; 4]);
+    }
+
+    #[test]
+    fn test_silence() {
+        let silence = Pcm::silence(Duration::from_millis(500), SAMPLE_RATE, CHANNELS);
+        assert_eq!(silence.samples.len(), 22050 * 2);
+        assert_eq!(silence.duration(), Duration::from_millis(500));
+        let wav = silence.to_wav_bytes();
+        assert_eq!(wav.len(), 44 + 22050 * 4);
+        assert!(is_wav(&wav));
+        assert!(!is_wav(b"ID3\x04\x00\x00\x00\x00\x00\x00\x00\x00"));
+    }
+}
@@ -0,0 +1,32 @@
+use crate::{
+    pcm::{blocking, Pcm, CHANNELS, SAMPLE_RATE},
+    workdir::WorkDir,
+    AudioGenerator, Clip,
+};
+use api::episode::Section;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+pub(crate) struct SilenceGenerator;
+
+#[async_trait]
+impl AudioGenerator for SilenceGenerator {
+    /// the clip has no subtitle
+    async fn generate(
+        &self,
+        work_dir: &WorkDir,
+        section: Section,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<Clip>> {
+        let Section::Silence { duration } = section else {
+            return Err(anyhow::anyhow!("Invalid segment"));
+        };
+        let path = work_dir.dir().join(format!("{}.wav", Uuid::new_v4()));
+        let silence = Pcm::silence(Duration::from_secs_f64(duration), SAMPLE_RATE, CHANNELS);
+        let out = path.clone();
+        blocking(move || silence.write(&out)).await?;
+        Ok(vec![Clip::new(path, String::new())])
+    }
+}
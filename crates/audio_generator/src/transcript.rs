@@ -0,0 +1,166 @@
+use srtlib::{Subtitle, Subtitles, Timestamp};
+
+/// a mora with its timing, from the start of the clip or the episode
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Mora {
+    pub text: String,
+    pub start_sec: f64,
+    pub end_sec: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptSentence {
+    pub text: String,
+    pub start_sec: f64,
+    pub end_sec: f64,
+    /// empty if the engine does not report timings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moras: Vec<Mora>,
+}
+
+/// timing of a section, sections without clips take no time
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptSection {
+    /// `type` of the section
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
+    pub start_sec: f64,
+    pub end_sec: f64,
+    pub sentences: Vec<TranscriptSentence>,
+}
+
+/// timings of an episode, in the order of its sections
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    pub duration_sec: f64,
+    pub sections: Vec<TranscriptSection>,
+}
+
+fn millis(sec: f64) -> u64 {
+    (sec * 1000.0).round() as u64
+}
+
+/// `HH:MM:SS.mmm`
+fn vtt_timestamp(sec: f64) -> String {
+    let ms = millis(sec);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl Transcript {
+    fn cues(&self) -> impl Iterator<Item = (&TranscriptSection, &TranscriptSentence)> {
+        self.sections.iter().flat_map(|section| {
+            section
+                .sentences
+                .iter()
+                .map(move |sentence| (section, sentence))
+        })
+    }
+
+    pub fn srt(&self) -> String {
+        let mut srt = Subtitles::new();
+        for (n, (_, sentence)) in self.cues().enumerate() {
+            srt.push(Subtitle::new(
+                n,
+                Timestamp::from_milliseconds(millis(sentence.start_sec) as u32),
+                Timestamp::from_milliseconds(millis(sentence.end_sec) as u32),
+                sentence.text.clone(),
+            ));
+        }
+        srt.to_string()
+    }
+
+    /// cues of serifs are tagged with `<v Speaker>`
+    pub fn webvtt(&self) -> String {
+        let mut vtt = "WEBVTT\n".to_string();
+        for (n, (section, sentence)) in self.cues().enumerate() {
+            let text = escape_vtt(&sentence.text);
+            let text = match section.speaker.as_ref() {
+                Some(speaker) => format!("<v {}>{}", escape_vtt(speaker), text),
+                None => text,
+            };
+            vtt.push_str(&format!(
+                "\n{}\n{} --> {}\n{}\n",
+                n + 1,
+                vtt_timestamp(sentence.start_sec),
+                vtt_timestamp(sentence.end_sec),
+                text
+            ));
+        }
+        vtt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript() -> Transcript {
+        Transcript {
+            duration_sec: 3723.5,
+            sections: vec![
+                TranscriptSection {
+                    kind: "Serif".to_string(),
+                    speaker: Some("ずんだもん".to_string()),
+                    start_sec: 0.0,
+                    end_sec: 1.25,
+                    sentences: vec![TranscriptSentence {
+                        text: "A<B & C".to_string(),
+                        start_sec: 0.0,
+                        end_sec: 1.25,
+                        moras: vec![],
+                    }],
+                },
+                TranscriptSection {
+                    kind: "Jingle".to_string(),
+                    speaker: None,
+                    start_sec: 1.25,
+                    end_sec: 3723.5,
+                    sentences: vec![TranscriptSentence {
+                        text: "♪".to_string(),
+                        start_sec: 1.25,
+                        end_sec: 3723.5,
+                        moras: vec![],
+                    }],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_webvtt() {
+        assert_eq!(
+            transcript().webvtt(),
+            [
+                "WEBVTT",
+                "",
+                "1",
+                "00:00:00.000 --> 00:00:01.250",
+                "<v ずんだもん>A&lt;B &amp; C",
+                "",
+                "2",
+                "00:00:01.250 --> 01:02:03.500",
+                "♪",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,348 @@
+pub mod text;
+pub mod tone;
+
+use crate::{
+    cache::{AudioCache, CacheKey},
+    dictionary::Dictionary,
+    pcm::wav_duration,
+    transcript::Mora,
+    voicevox::client::VoiceVoxClient,
+    workdir::WorkDir,
+    AudioGenerator, Clip,
+};
+use anyhow::{Context, Result};
+use api::episode::{Section, VoiceParams};
+use async_trait::async_trait;
+use futures::{stream, StreamExt, TryStreamExt};
+use std::{collections::BTreeMap, path::PathBuf};
+use text::{normalize, split_text, strip_markdown};
+use tokio::{
+    fs,
+    sync::{OnceCell, Semaphore},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// a synthesized sentence
+#[derive(Debug, Clone)]
+pub struct Synthesis {
+    pub wav: Vec<u8>,
+    /// from the start of the wav, empty if the engine does not report timings
+    pub moras: Vec<Mora>,
+}
+
+/// a text-to-speech engine which synthesizes a sentence at once
+#[async_trait]
+pub trait TtsEngine: Send + Sync {
+    /// identifies the build of the engine, a part of cache keys
+    async fn version(&self) -> Result<String>;
+
+    async fn synthesize(
+        &self,
+        text: &str,
+        speaker: &str,
+        params: &VoiceParams,
+    ) -> Result<Synthesis>;
+}
+
+/// `<engine>:<id>` e.g. `voicevox:3`, or `<id>` of the default engine
+#[derive(Debug, PartialEq)]
+pub struct SpeakerId<'a> {
+    pub engine: &'a str,
+    pub id: &'a str,
+}
+
+impl<'a> SpeakerId<'a> {
+    pub fn parse(speaker: &'a str, default_engine: &'a str) -> Self {
+        match speaker.split_once(':') {
+            Some((engine, id)) => Self { engine, id },
+            None => Self {
+                engine: default_engine,
+                id: speaker,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum EngineConfig {
+    /// VOICEVOX and engines with a compatible API
+    #[serde(alias = "aivisspeech", alias = "sharevox")]
+    Voicevox { endpoint: String },
+    /// offline engine emitting deterministic tones
+    Tone,
+}
+
+/// the content of `TTS_CONFIG`, see `tts.toml`
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TtsConfig {
+    /// engine of speakers without an engine prefix
+    #[serde(default = "TtsConfig::default_engine")]
+    pub default_engine: String,
+    /// max number of sentences synthesized at once per engine
+    #[serde(default = "TtsConfig::default_concurrency")]
+    pub concurrency: usize,
+    pub engines: BTreeMap<String, EngineConfig>,
+}
+
+impl TtsConfig {
+    fn default_engine() -> String {
+        "voicevox".to_string()
+    }
+
+    fn default_concurrency() -> usize {
+        4
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// reads `TTS_CONFIG` (default `tts.toml`),
+    /// or falls back to the deprecated `VOICEVOX_ENDPOINT` if the file does not exist
+    pub fn load() -> Result<Self> {
+        let path = std::env::var("TTS_CONFIG").unwrap_or("tts.toml".to_string());
+        if !std::path::Path::new(&path).exists() {
+            if let Ok(endpoint) = std::env::var("VOICEVOX_ENDPOINT") {
+                tracing::warn!("{} is not found, VOICEVOX_ENDPOINT is deprecated", path);
+                return Ok(Self {
+                    default_engine: Self::default_engine(),
+                    concurrency: Self::default_concurrency(),
+                    engines: BTreeMap::from([(
+                        Self::default_engine(),
+                        EngineConfig::Voicevox { endpoint },
+                    )]),
+                });
+            }
+        }
+        let s = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read tts config {}", path))?;
+        Self::from_toml(&s).with_context(|| format!("Invalid tts config {}", path))
+    }
+}
+
+struct Engine {
+    inner: Box<dyn TtsEngine>,
+    version: OnceCell<String>,
+    /// bounds concurrent requests to the engine
+    permits: Semaphore,
+}
+
+impl Engine {
+    async fn version(&self) -> Result<&str> {
+        let version = self
+            .version
+            .get_or_try_init(|| self.inner.version())
+            .await?;
+        Ok(version)
+    }
+}
+
+/// synthesizes serifs with the engine chosen by the speaker id.
+/// built once and shared, so the concurrency of an engine is bounded across tasks
+pub struct TtsRegistry {
+    engines: BTreeMap<String, Engine>,
+    default_engine: String,
+    pub(crate) concurrency: usize,
+    cache: AudioCache,
+}
+
+impl std::fmt::Debug for TtsRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TtsRegistry")
+            .field("engines", &self.engines.keys().collect::<Vec<_>>())
+            .field("default_engine", &self.default_engine)
+            .field("concurrency", &self.concurrency)
+            .finish()
+    }
+}
+
+/// the registry reading serifs with a dictionary applied
+pub struct DictionaryTts<'a> {
+    tts: &'a TtsRegistry,
+    dictionary: &'a Dictionary,
+}
+
+impl TtsRegistry {
+    pub fn new(config: TtsConfig) -> Self {
+        let concurrency = config.concurrency.max(1);
+        let engines = config
+            .engines
+            .into_iter()
+            .map(|(name, engine)| {
+                let inner: Box<dyn TtsEngine> = match engine {
+                    EngineConfig::Voicevox { endpoint } => Box::new(VoiceVoxClient::new(endpoint)),
+                    EngineConfig::Tone => Box::new(tone::ToneEngine::new()),
+                };
+                let engine = Engine {
+                    inner,
+                    version: OnceCell::new(),
+                    permits: Semaphore::new(concurrency),
+                };
+                (name, engine)
+            })
+            .collect();
+        Self {
+            engines,
+            default_engine: config.default_engine,
+            concurrency,
+            cache: AudioCache::from_env(),
+        }
+    }
+
+    pub fn with_dictionary<'a>(&'a self, dictionary: &'a Dictionary) -> DictionaryTts<'a> {
+        DictionaryTts {
+            tts: self,
+            dictionary,
+        }
+    }
+
+    /// synthesizes a sentence into the work dir, reusing the cache if possible
+    async fn synthesize_sentence(
+        &self,
+        work_dir: &WorkDir,
+        sentence: &str,
+        speaker: &SpeakerId<'_>,
+        params: &VoiceParams,
+        dictionary: &Dictionary,
+        cancel: &CancellationToken,
+    ) -> Result<(PathBuf, Vec<Mora>)> {
+        let engine = self
+            .engines
+            .get(speaker.engine)
+            .with_context(|| format!("Unknown tts engine: {}", speaker.engine))?;
+        // NOTE: subtitles keep the written sentence, engines read the normalized one
+        let spoken = normalize(&dictionary.apply(sentence));
+        let hash = CacheKey {
+            engine: speaker.engine,
+            engine_version: engine.version().await?,
+            speaker: speaker.id,
+            text: &spoken,
+            params: &serde_json::to_value(params)?,
+        }
+        .hash();
+        let wav_path = work_dir.dir().join(format!("{}.wav", hash));
+        let moras_path = work_dir.dir().join(format!("{}.moras.json", hash));
+        if fs::try_exists(&wav_path).await? || self.cache.get(&hash, "wav", &wav_path).await? {
+            // NOTE: clips cached before timings were recorded have no moras
+            let moras = if fs::try_exists(&moras_path).await?
+                || self.cache.get(&hash, "moras.json", &moras_path).await?
+            {
+                serde_json::from_slice(&fs::read(&moras_path).await?)?
+            } else {
+                vec![]
+            };
+            return Ok((wav_path, moras));
+        }
+
+        let _permit = engine.permits.acquire().await?;
+        if cancel.is_cancelled() {
+            anyhow::bail!("Cancelled");
+        }
+        let synthesis = engine.inner.synthesize(&spoken, speaker.id, params).await?;
+
+        // NOTE: sentences are synthesized concurrently, so intermediate files are unique
+        // and the clip is renamed into place, even if the same sentence is in flight twice
+        let id = Uuid::new_v4();
+        let raw_path = work_dir.dir().join(format!("{}.raw.wav", id));
+        fs::write(&raw_path, &synthesis.wav).await?;
+        // NOTE: clips are conformed to the speech format on concatenation
+        wav_duration(&raw_path).context("Invalid wav from the engine")?;
+        // NOTE: a clip is present once its wav is, so its moras are published first
+        let raw_moras_path = work_dir.dir().join(format!("{}.moras.json", id));
+        fs::write(&raw_moras_path, serde_json::to_vec(&synthesis.moras)?).await?;
+        fs::rename(&raw_moras_path, &moras_path).await?;
+        fs::rename(&raw_path, &wav_path).await?;
+        self.cache.put(&hash, "moras.json", &moras_path).await?;
+        self.cache.put(&hash, "wav", &wav_path).await?;
+        Ok((wav_path, synthesis.moras))
+    }
+}
+
+#[async_trait]
+impl AudioGenerator for DictionaryTts<'_> {
+    #[instrument(skip(self, work_dir, cancel))]
+    async fn generate(
+        &self,
+        work_dir: &WorkDir,
+        section: Section,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Clip>> {
+        let Section::Serif {
+            text,
+            speaker,
+            params,
+            ..
+        } = section
+        else {
+            return Err(anyhow::anyhow!("Invalid segment"));
+        };
+        params.validate()?;
+        let speaker = SpeakerId::parse(&speaker, &self.tts.default_engine);
+
+        // NOTE: `buffered` keeps the order of sentences
+        stream::iter(split_text(&strip_markdown(&text), 100))
+            .map(|sentence| async {
+                tracing::info!("{}", sentence);
+                let (path, moras) = self
+                    .tts
+                    .synthesize_sentence(
+                        work_dir,
+                        &sentence,
+                        &speaker,
+                        &params,
+                        self.dictionary,
+                        cancel,
+                    )
+                    .await?;
+                Ok(Clip {
+                    path,
+                    text: sentence,
+                    moras,
+                })
+            })
+            .buffered(self.tts.concurrency)
+            .try_collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speaker_id() {
+        assert_eq!(
+            SpeakerId::parse("aivisspeech:888753760", "voicevox"),
+            SpeakerId {
+                engine: "aivisspeech",
+                id: "888753760"
+            }
+        );
+        assert_eq!(
+            SpeakerId::parse("3", "voicevox"),
+            SpeakerId {
+                engine: "voicevox",
+                id: "3"
+            }
+        );
+    }
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let config = TtsConfig::from_toml(include_str!("../../../../tts.toml"))?;
+        assert_eq!(config.default_engine, "voicevox");
+        assert!(matches!(
+            config.engines.get("aivisspeech"),
+            Some(EngineConfig::Voicevox { .. })
+        ));
+        assert!(matches!(
+            config.engines.get("tone"),
+            Some(EngineConfig::Tone)
+        ));
+        Ok(())
+    }
+}
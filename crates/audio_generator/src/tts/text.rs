@@ -0,0 +1,649 @@
+//! text read by engines: markdown stripping, sentence splitting and normalization into readings
+
+/// ends a sentence
+const TERMINATORS: [char; 5] = ['。', '！', '？', '\n', '…'];
+/// belongs to the sentence before, e.g. `」` of `「はい。」`
+const CLOSERS: [char; 10] = ['」', '』', '）', ')', '】', '〉', '"', '”', '’', '\''];
+/// where a long sentence may be broken, preferred from the left
+const CLAUSE_BREAKS: [&[char]; 3] = [&['、', '，', ',', '；', ';'], &['：', ':'], &[' ', '　']];
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// removes markdown syntax, keeping what is read aloud. code blocks are not read
+pub fn strip_markdown(text: &str) -> String {
+    let mut lines = vec![];
+    let mut in_code = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code || is_rule(trimmed) || is_table_separator(trimmed) {
+            continue;
+        }
+        let line = strip_block(trimmed);
+        let line = if line.starts_with('|') && line.ends_with('|') && line.len() > 1 {
+            line[1..line.len() - 1]
+                .split('|')
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join("、")
+        } else {
+            line.to_string()
+        };
+        lines.push(strip_inline(&line));
+    }
+    lines.join("\n")
+}
+
+fn is_rule(line: &str) -> bool {
+    line.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|&c| line.chars().all(|l| l == c || l == ' '))
+}
+
+fn is_table_separator(line: &str) -> bool {
+    line.contains('|')
+        && line.contains('-')
+        && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+/// headings, quotes and list markers
+fn strip_block(mut line: &str) -> &str {
+    loop {
+        let stripped = line
+            .trim_start_matches('#')
+            .strip_prefix(' ')
+            .filter(|_| line.starts_with('#'))
+            .or_else(|| line.strip_prefix('>'))
+            .or_else(|| line.strip_prefix("- "))
+            .or_else(|| line.strip_prefix("* "))
+            .or_else(|| line.strip_prefix("+ "))
+            .or_else(|| {
+                let digits = line.chars().take_while(char::is_ascii_digit).count();
+                line[digits..].strip_prefix(". ").filter(|_| digits > 0)
+            });
+        match stripped {
+            Some(stripped) => line = stripped.trim_start(),
+            None => return line,
+        }
+    }
+}
+
+/// links, images, code spans and emphasis
+fn strip_inline(line: &str) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut res = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let is_image = c == '!' && chars.get(i + 1) == Some(&'[');
+        if c == '[' || is_image {
+            let start = if is_image { i + 2 } else { i + 1 };
+            if let Some((label, end)) = link(&chars, start) {
+                res.push_str(&strip_inline(&label));
+                i = end;
+                continue;
+            }
+        }
+        match c {
+            '`' | '*' | '~' => {}
+            '_' if chars.get(i + 1) == Some(&'_') => i += 1,
+            _ => res.push(c),
+        }
+        i += 1;
+    }
+    res
+}
+
+/// `label](url)` from `start`, returns the label and the index after `)`
+fn link(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let close = start + chars[start..].iter().position(|&c| c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 2 + chars[close + 2..].iter().position(|&c| c == ')')?;
+    Some((chars[start..close].iter().collect(), end + 1))
+}
+
+/// whether the character at `i` ends a sentence,
+/// ascii ones do not inside words, numbers and urls e.g. `3.14` or `?a=1`
+fn ends_sentence(chars: &[char], i: usize) -> bool {
+    TERMINATORS.contains(&chars[i])
+        || matches!(chars[i], '.' | '!' | '?')
+            && !chars.get(i + 1).is_some_and(|c| c.is_ascii_graphic())
+}
+
+/// sentences with their terminators and closers, without empty ones
+fn sentences(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut res = vec![];
+    let mut buf = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        buf.push(chars[i]);
+        if ends_sentence(&chars, i) {
+            while i + 1 < chars.len()
+                && (ends_sentence(&chars, i + 1) || CLOSERS.contains(&chars[i + 1]))
+            {
+                i += 1;
+                buf.push(chars[i]);
+            }
+            let sentence = buf.trim();
+            if !sentence.is_empty() {
+                res.push(sentence.to_string());
+            }
+            buf.clear();
+        }
+        i += 1;
+    }
+    let sentence = buf.trim();
+    if !sentence.is_empty() {
+        res.push(sentence.to_string());
+    }
+    res
+}
+
+/// breaks a sentence longer than `max_chars` at clauses, or at words as a last resort
+fn break_long(sentence: &str, max_chars: usize) -> Vec<String> {
+    let mut rest = sentence.chars().collect::<Vec<_>>();
+    let mut res = vec![];
+    while rest.len() > max_chars {
+        let head = &rest[..max_chars];
+        let at = CLAUSE_BREAKS
+            .iter()
+            .find_map(|breaks| {
+                head.iter()
+                    .rposition(|c| breaks.contains(c))
+                    .filter(|&i| i > 0)
+                    .map(|i| i + 1)
+            })
+            .or_else(|| {
+                // NOTE: avoid cutting an alphanumeric word in half
+                head.iter()
+                    .rposition(|c| !c.is_ascii_alphanumeric())
+                    .filter(|&i| i >= max_chars / 2 && rest[max_chars].is_ascii_alphanumeric())
+                    .map(|i| i + 1)
+            })
+            .unwrap_or(max_chars);
+        let chunk = rest.drain(..at).collect::<String>();
+        res.push(chunk.trim().to_string());
+    }
+    let chunk = rest.into_iter().collect::<String>();
+    res.push(chunk.trim().to_string());
+    res.retain(|chunk| !chunk.is_empty());
+    res
+}
+
+/// splits into sentences, and packs them into chunks of at most `max_chars` characters
+pub fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut res = vec![];
+    let mut buf = String::new();
+    for sentence in sentences(text)
+        .iter()
+        .flat_map(|sentence| break_long(sentence, max_chars))
+    {
+        if !buf.is_empty() && char_len(&buf) + char_len(&sentence) > max_chars {
+            res.push(std::mem::take(&mut buf));
+        }
+        // NOTE: english sentences are separated by a space
+        if buf.ends_with(|c: char| c.is_ascii_punctuation() || c.is_ascii_alphanumeric())
+            && sentence.starts_with(|c: char| c.is_ascii_alphanumeric())
+        {
+            buf.push(' ');
+        }
+        buf.push_str(&sentence);
+    }
+    if !buf.is_empty() {
+        res.push(buf);
+    }
+    res
+}
+
+const DIGITS: [&str; 10] = ["零", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+/// e.g. `2024` to `二千二十四`
+fn kanji_number(n: u64) -> String {
+    if n == 0 {
+        return DIGITS[0].to_string();
+    }
+    let below_10000 = |n: u64| {
+        let mut res = String::new();
+        for (unit, d) in [("千", 1000), ("百", 100), ("十", 10)] {
+            let q = n / d % 10;
+            if q > 1 {
+                res.push_str(DIGITS[q as usize]);
+            }
+            if q > 0 {
+                res.push_str(unit);
+            }
+        }
+        if n % 10 > 0 {
+            res.push_str(DIGITS[(n % 10) as usize]);
+        }
+        res
+    };
+    let mut res = String::new();
+    for (unit, d) in [
+        ("京", 10u64.pow(16)),
+        ("兆", 10u64.pow(12)),
+        ("億", 10u64.pow(8)),
+        ("万", 10u64.pow(4)),
+        ("", 1),
+    ] {
+        let group = n / d % 10000;
+        if group > 0 {
+            res.push_str(&below_10000(group));
+            res.push_str(unit);
+        }
+    }
+    res
+}
+
+/// digits one by one, e.g. phone numbers
+fn kanji_digits(digits: &str) -> String {
+    digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| if d == 0 { "〇" } else { DIGITS[d as usize] })
+        .collect()
+}
+
+/// units read after numbers, longer ones first
+const UNITS: [(&str, &str); 19] = [
+    ("km/h", "キロメートル毎時"),
+    ("°C", "度"),
+    ("℃", "度"),
+    ("%", "パーセント"),
+    ("km", "キロメートル"),
+    ("cm", "センチメートル"),
+    ("mm", "ミリメートル"),
+    ("kg", "キログラム"),
+    ("mg", "ミリグラム"),
+    ("ms", "ミリ秒"),
+    ("KB", "キロバイト"),
+    ("MB", "メガバイト"),
+    ("GB", "ギガバイト"),
+    ("TB", "テラバイト"),
+    ("GHz", "ギガヘルツ"),
+    ("Hz", "ヘルツ"),
+    ("m", "メートル"),
+    ("g", "グラム"),
+    ("h", "時間"),
+];
+
+const CURRENCIES: [(char, &str); 4] = [('$', "ドル"), ('¥', "円"), ('￥', "円"), ('€', "ユーロ")];
+
+/// a scanner over characters of the text being normalized
+struct Scanner {
+    chars: Vec<char>,
+    i: usize,
+}
+
+impl Scanner {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.i + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(k, c)| self.peek(k) == Some(c))
+    }
+
+    /// ascii digits from `offset`
+    fn digits(&self, offset: usize) -> String {
+        (offset..)
+            .map_while(|k| self.peek(k).filter(char::is_ascii_digit))
+            .collect()
+    }
+
+    /// `YYYY/MM/DD` or `YYYY-MM-DD`
+    fn date(&mut self) -> Option<String> {
+        let year = self.digits(0);
+        let sep = self.peek(year.len()).filter(|c| matches!(c, '/' | '-'))?;
+        let month = self.digits(year.len() + 1);
+        let day = self.digits(year.len() + month.len() + 2);
+        let len = year.len() + month.len() + day.len() + 2;
+        if year.len() != 4
+            || self.peek(year.len() + month.len() + 1) != Some(sep)
+            || !(1..=2).contains(&month.len())
+            || !(1..=2).contains(&day.len())
+            || self.peek(len).is_some_and(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let (month, day) = (month.parse::<u64>().ok()?, day.parse::<u64>().ok()?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        let year = year.parse().ok()?;
+        self.i += len;
+        Some(format!(
+            "{}年{}月{}日",
+            kanji_number(year),
+            kanji_number(month),
+            kanji_number(day)
+        ))
+    }
+
+    /// `H:MM`
+    fn time(&mut self) -> Option<String> {
+        let hour = self.digits(0);
+        let minute = self.digits(hour.len() + 1);
+        let len = hour.len() + minute.len() + 1;
+        if !(1..=2).contains(&hour.len())
+            || self.peek(hour.len()) != Some(':')
+            || minute.len() != 2
+            || self
+                .peek(len)
+                .is_some_and(|c| c.is_ascii_digit() || c == ':')
+        {
+            return None;
+        }
+        let (hour, minute) = (hour.parse::<u64>().ok()?, minute.parse::<u64>().ok()?);
+        if hour > 24 || minute > 59 {
+            return None;
+        }
+        self.i += len;
+        Some(match minute {
+            0 => format!("{}時", kanji_number(hour)),
+            _ => format!("{}時{}分", kanji_number(hour), kanji_number(minute)),
+        })
+    }
+
+    /// integers with optional thousands separators and decimals
+    fn number(&mut self) -> Option<String> {
+        let mut integer = self.digits(0);
+        if integer.is_empty() {
+            return None;
+        }
+        let mut len = integer.len();
+        // NOTE: `1,000` but not `1,2` nor `12345,678`
+        if integer.len() <= 3 {
+            while self.peek(len) == Some(',') {
+                let group = self.digits(len + 1);
+                if group.len() != 3 {
+                    break;
+                }
+                integer.push_str(&group);
+                len += 4;
+            }
+        }
+        let mut decimals = String::new();
+        if self.peek(len) == Some('.') {
+            decimals = self.digits(len + 1);
+            if !decimals.is_empty() {
+                len += decimals.len() + 1;
+            }
+        }
+        self.i += len;
+        // NOTE: codes like `090` or overly long numbers are read digit by digit
+        let integer_reading = if integer.len() > 2 && integer.starts_with('0') || integer.len() > 16
+        {
+            kanji_digits(&integer)
+        } else {
+            kanji_number(integer.parse().ok()?)
+        };
+        Some(match decimals.is_empty() {
+            true => integer_reading,
+            false => format!("{}点{}", integer_reading, kanji_digits(&decimals)),
+        })
+    }
+
+    fn unit(&mut self) -> Option<&'static str> {
+        let offset = usize::from(self.peek(0) == Some(' '));
+        let (unit, reading) = UNITS.iter().find(|(unit, _)| {
+            unit.chars()
+                .enumerate()
+                .all(|(k, c)| self.peek(offset + k) == Some(c))
+                && !self
+                    .peek(offset + unit.chars().count())
+                    .is_some_and(|c| c.is_ascii_alphanumeric())
+        })?;
+        self.i += offset + unit.chars().count();
+        Some(reading)
+    }
+
+    /// the host of a url, e.g. `example ドット com`
+    fn url(&mut self) -> Option<String> {
+        if !self.starts_with("http://") && !self.starts_with("https://") {
+            return None;
+        }
+        let len = (0..)
+            .take_while(|&k| {
+                self.peek(k)
+                    .is_some_and(|c| c.is_ascii_graphic() && !matches!(c, '"' | '<' | '>' | '）'))
+            })
+            .count();
+        let url = self.chars[self.i..self.i + len].iter().collect::<String>();
+        self.i += len;
+        let host = url
+            .split("://")
+            .nth(1)?
+            .split(['/', '?', '#', ':'])
+            .next()?
+            .trim_start_matches("www.");
+        Some(host.split('.').collect::<Vec<_>>().join(" ドット "))
+    }
+}
+
+/// half-width digits and symbols, so that `２０２４年` is read as a number
+fn to_half_width(c: char) -> char {
+    match c {
+        '０'..='９' | '％' | '．' | '，' | '：' | '／' => {
+            char::from_u32(c as u32 - 0xfee0).unwrap_or(c)
+        }
+        _ => c,
+    }
+}
+
+/// rewrites urls, dates, times, numbers, currencies and units into readable japanese
+pub fn normalize(text: &str) -> String {
+    let mut scanner = Scanner {
+        chars: text.chars().map(to_half_width).collect(),
+        i: 0,
+    };
+    let mut res = String::with_capacity(text.len());
+    while let Some(c) = scanner.peek(0) {
+        let after_word = scanner.i > 0 && scanner.chars[scanner.i - 1].is_ascii_alphanumeric();
+        if let Some(host) = scanner.url() {
+            res.push_str(&host);
+            continue;
+        }
+        if let Some((_, currency)) = CURRENCIES.iter().find(|(symbol, _)| *symbol == c) {
+            if scanner.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+                scanner.i += 1;
+                if let Some(number) = scanner.number() {
+                    res.push_str(&number);
+                    res.push_str(currency);
+                    continue;
+                }
+            }
+        }
+        if c.is_ascii_digit() && !after_word {
+            if let Some(reading) = scanner.date().or_else(|| scanner.time()) {
+                res.push_str(&reading);
+                continue;
+            }
+            if let Some(number) = scanner.number() {
+                res.push_str(&number);
+                if let Some(unit) = scanner.unit() {
+                    res.push_str(unit);
+                }
+                continue;
+            }
+        }
+        res.push(c);
+        scanner.i += 1;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_text() {
+        assert_eq!(
+            split_text("こんにちは。元気ですか？はい！", 100),
+            vec!["こんにちは。元気ですか？はい！"]
+        );
+        assert_eq!(
+            split_text("こんにちは。元気ですか？はい！", 9),
+            vec!["こんにちは。", "元気ですか？はい！"]
+        );
+        // NOTE: sizes are in characters, not in bytes
+        assert_eq!(
+            split_text("あいうえお。かきくけこ。", 12),
+            vec!["あいうえお。かきくけこ。"]
+        );
+        assert_eq!(split_text("", 10), Vec::<String>::new());
+        assert_eq!(split_text("\n\n。", 10), vec!["。"]);
+    }
+
+    #[test]
+    fn test_split_text_no_empty_first_chunk() {
+        let long = "あ".repeat(20) + "。";
+        let chunks = split_text(&long, 10);
+        assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+        assert!(chunks.iter().all(|chunk| char_len(chunk) <= 10));
+        assert_eq!(chunks.concat(), long);
+    }
+
+    #[test]
+    fn test_split_text_keeps_urls() {
+        assert_eq!(
+            split_text("URLです。\nhttps://example.com/a?b=1\n以上。", 25),
+            vec!["URLです。", "https://example.com/a?b=1", "以上。"]
+        );
+        assert_eq!(
+            split_text("http://example.com が公式です。", 100),
+            vec!["http://example.com が公式です。"]
+        );
+    }
+
+    #[test]
+    fn test_split_text_closers() {
+        assert_eq!(
+            split_text("彼は「はい。」と言った。そうですか！？本当に。", 10),
+            vec!["彼は「はい。」", "と言った。", "そうですか！？", "本当に。"]
+        );
+    }
+
+    #[test]
+    fn test_split_text_english() {
+        assert_eq!(
+            split_text("Hello world. This costs 3.14 dollars. OK?", 20),
+            vec!["Hello world.", "This costs 3.14", "dollars. OK?"]
+        );
+    }
+
+    #[test]
+    fn test_break_long() {
+        assert_eq!(
+            break_long("今日は晴れ、明日は雨、明後日は雪です。", 12),
+            vec!["今日は晴れ、明日は雨、", "明後日は雪です。"]
+        );
+        assert_eq!(
+            break_long("すなわち：それは問題ではない", 8),
+            vec!["すなわち：", "それは問題ではな", "い"]
+        );
+        assert_eq!(
+            break_long("use the botcast worker", 10),
+            vec!["use the", "botcast", "worker"]
+        );
+    }
+
+    #[test]
+    fn test_strip_markdown() {
+        let markdown = [
+            "# 今日の**ニュース**",
+            "",
+            "> 引用です",
+            "- [記事](https://example.com)を読む",
+            "1. `cargo` を使う",
+            "![画像](a.png)",
+            "---",
+            "| 名前 | 値 |",
+            "|---|---|",
+            "| a | 1 |",
+            "```rust",
+            "fn main() {}",
+            "```",
+            "snake_case と __強調__",
+        ]
+        .join("\n");
+        assert_eq!(
+            strip_markdown(&markdown),
+            [
+                "今日のニュース",
+                "",
+                "引用です",
+                "記事を読む",
+                "cargo を使う",
+                "画像",
+                "名前、値",
+                "a、1",
+                "snake_case と 強調",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_kanji_number() {
+        assert_eq!(kanji_number(0), "零");
+        assert_eq!(kanji_number(10), "十");
+        assert_eq!(kanji_number(11), "十一");
+        assert_eq!(kanji_number(2024), "二千二十四");
+        assert_eq!(kanji_number(100_000), "十万");
+        assert_eq!(kanji_number(1_0001_0000), "一億一万");
+        assert_eq!(kanji_number(1_1000), "一万千");
+    }
+
+    #[test]
+    fn test_normalize_numbers() {
+        assert_eq!(normalize("1,234,567人"), "百二十三万四千五百六十七人");
+        assert_eq!(normalize("円周率は3.14"), "円周率は三点一四");
+        assert_eq!(normalize("０９０１２３４"), "〇九〇一二三四");
+        assert_eq!(normalize("２０２４年"), "二千二十四年");
+        assert_eq!(normalize("1,2"), "一,二");
+        assert_eq!(normalize("12345,678"), "一万二千三百四十五,六百七十八");
+        assert_eq!(normalize("007"), "〇〇七");
+        assert_eq!(normalize("GPT4"), "GPT4");
+    }
+
+    #[test]
+    fn test_normalize_dates() {
+        assert_eq!(normalize("2024/10/18に"), "二千二十四年十月十八日に");
+        assert_eq!(normalize("2024-1-5"), "二千二十四年一月五日");
+        assert_eq!(normalize("9:00から12:30まで"), "九時から十二時三十分まで");
+        assert_eq!(normalize("2024/13/01"), "二千二十四/十三/一");
+    }
+
+    #[test]
+    fn test_normalize_units() {
+        assert_eq!(normalize("50%"), "五十パーセント");
+        assert_eq!(
+            normalize("時速100km/hで5 km"),
+            "時速百キロメートル毎時で五キロメートル"
+        );
+        assert_eq!(normalize("気温は25℃"), "気温は二十五度");
+        assert_eq!(normalize("$100と¥500"), "百ドルと五百円");
+        assert_eq!(normalize("16GBと3min"), "十六ギガバイトと三min");
+    }
+
+    #[test]
+    fn test_normalize_urls() {
+        assert_eq!(
+            normalize("詳しくは https://www.example.com/path?a=1 を見て"),
+            "詳しくは example ドット com を見て"
+        );
+        assert_eq!(normalize("(http://a.jp)"), "(a ドット jp)");
+    }
+}
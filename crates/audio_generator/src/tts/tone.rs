@@ -0,0 +1,122 @@
+use super::{Synthesis, TtsEngine};
+use crate::{pcm::Pcm, transcript::Mora};
+use anyhow::Result;
+use api::episode::VoiceParams;
+use async_trait::async_trait;
+use std::f64::consts::PI;
+
+const SAMPLE_RATE: u32 = 24000;
+const CHANNELS: u16 = 2;
+/// length of a tone per character
+const MS_PER_CHAR: u32 = 80;
+
+/// offline engine for tests, emits a tone whose pitch depends on the speaker
+/// and whose length depends on the text, between silences of phoneme lengths
+#[derive(Debug, Clone, Default)]
+pub struct ToneEngine;
+
+impl ToneEngine {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn frequency(speaker: &str) -> f64 {
+        let n = speaker
+            .bytes()
+            .fold(0u32, |acc, b| acc.wrapping_add(b as u32));
+        220.0 * 2f64.powf((n % 12) as f64 / 12.0)
+    }
+
+    /// a character is a mora
+    pub(crate) fn moras(text: &str, params: &VoiceParams) -> Vec<Mora> {
+        let pre = params.pre_phoneme_length.unwrap_or(0.1);
+        let length = MS_PER_CHAR as f64 / 1000.0 / params.speed_scale.unwrap_or(1.0);
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| Mora {
+                text: c.to_string(),
+                start_sec: pre + length * i as f64,
+                end_sec: pre + length * (i + 1) as f64,
+            })
+            .collect()
+    }
+
+    /// follows VOICEVOX in the meaning of params
+    pub(crate) fn render(text: &str, speaker: &str, params: &VoiceParams) -> Vec<u8> {
+        let samples = |sec: f64| (sec * SAMPLE_RATE as f64) as u32;
+        let speech_sec = MS_PER_CHAR as f64 * text.chars().count() as f64 / 1000.0;
+        let pre = samples(params.pre_phoneme_length.unwrap_or(0.1));
+        let speech = samples(speech_sec / params.speed_scale.unwrap_or(1.0));
+        let post = samples(params.post_phoneme_length.unwrap_or(0.1));
+        let n_samples = pre + speech + post;
+        // pitchScale shifts pitch by octaves
+        let frequency = Self::frequency(speaker) * 2f64.powf(params.pitch_scale.unwrap_or(0.0));
+        let amplitude = 0.2 * params.volume_scale.unwrap_or(1.0) * i16::MAX as f64;
+
+        let mut data = Vec::with_capacity((n_samples * CHANNELS as u32) as usize);
+        for i in 0..n_samples {
+            let t = i as f64 / SAMPLE_RATE as f64;
+            let sample = if (pre..pre + speech).contains(&i) {
+                ((2.0 * PI * frequency * t).sin() * amplitude) as i16
+            } else {
+                0
+            };
+            data.extend((0..CHANNELS).map(|_| sample));
+        }
+        Pcm {
+            sample_rate: SAMPLE_RATE,
+            n_channels: CHANNELS,
+            samples: data,
+        }
+        .to_wav_bytes()
+    }
+}
+
+#[async_trait]
+impl TtsEngine for ToneEngine {
+    async fn version(&self) -> Result<String> {
+        Ok(env!("CARGO_PKG_VERSION").to_string())
+    }
+
+    async fn synthesize(
+        &self,
+        text: &str,
+        speaker: &str,
+        params: &VoiceParams,
+    ) -> Result<Synthesis> {
+        Ok(Synthesis {
+            wav: Self::render(text, speaker, params),
+            moras: Self::moras(text, params),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let params = VoiceParams {
+            pre_phoneme_length: Some(0.0),
+            post_phoneme_length: Some(0.0),
+            ..Default::default()
+        };
+        let wav = ToneEngine::render("こんにちは", "1", &params);
+        // 5 chars * 80ms * 24kHz * 2ch * 16bit
+        assert_eq!(wav.len(), 44 + 9600 * 4);
+        assert_eq!(wav, ToneEngine::render("こんにちは", "1", &params));
+        assert_ne!(wav, ToneEngine::render("こんにちは", "2", &params));
+
+        let fast = VoiceParams {
+            speed_scale: Some(2.0),
+            ..params
+        };
+        assert_eq!(
+            ToneEngine::render("こんにちは", "1", &fast).len(),
+            44 + 4800 * 4
+        );
+        let moras = ToneEngine::moras("こんにちは", &fast);
+        assert_eq!((moras[1].text.as_str(), moras[1].start_sec), ("ん", 0.04));
+    }
+}
@@ -1,6 +1,7 @@
 use serde_json::Value;
 use tracing::instrument;
 
+/// client of VOICEVOX and engines with a compatible API
 #[derive(Debug, Clone)]
 pub struct VoiceVoxClient {
     endpoint: String,
@@ -16,7 +17,7 @@ impl VoiceVoxClient {
         }
     }
 
-    async fn _version(&self) -> anyhow::Result<Value> {
+    pub(crate) async fn version(&self) -> anyhow::Result<Value> {
         let url = format!("{}/version", self.endpoint);
         let res = self.client.get(url).send().await?;
         if res.status() != reqwest::StatusCode::OK {
@@ -1,86 +1,134 @@
 pub mod client;
 
-use crate::{ffmpeg::convert_to_stereo_wav, workdir::WorkDir, AudioGenerator};
+use crate::{
+    transcript::Mora,
+    tts::{Synthesis, TtsEngine},
+};
 use anyhow::Result;
-use api::episode::Section;
+use api::episode::VoiceParams;
 use async_trait::async_trait;
 use client::VoiceVoxClient;
-use std::{
-    fs::{self, OpenOptions},
-    io::Write,
-    path::PathBuf,
-};
-use tracing::instrument;
-use wavers::Wav;
+use serde_json::Value;
 
-const DELIMITERS: [char; 2] = ['\n', '。'];
+/// overwrites fields of the AudioQuery with the given params
+fn merge_params(query: &mut Value, params: &VoiceParams) -> Result<()> {
+    let Value::Object(query) = query else {
+        anyhow::bail!("AudioQuery is not an object: {}", query);
+    };
+    let Value::Object(params) = serde_json::to_value(params)? else {
+        unreachable!("VoiceParams is a struct");
+    };
+    query.extend(params);
+    Ok(())
+}
+
+fn length(value: &Value) -> f64 {
+    value.as_f64().unwrap_or(0.0)
+}
 
-fn split_text(text: &str, size: usize) -> Vec<String> {
-    let mut buf = String::new();
-    let mut res = vec![];
-    for sentence in text.split_inclusive(DELIMITERS) {
-        let sentence = sentence.trim();
-        if sentence.is_empty() || sentence.starts_with("http") {
-            continue;
+/// timing of moras as the engine lays them out:
+/// pre phoneme, consonants and vowels of accent phrases and pauses,
+/// all divided by `speedScale`
+fn mora_timing(query: &Value) -> Vec<Mora> {
+    let speed = query["speedScale"].as_f64().unwrap_or(1.0);
+    let pause_scale = query["pauseLengthScale"].as_f64().unwrap_or(1.0);
+    let mut t = length(&query["prePhonemeLength"]) / speed;
+    let mut moras = vec![];
+    let empty = vec![];
+    for phrase in query["accent_phrases"].as_array().unwrap_or(&empty) {
+        for mora in phrase["moras"].as_array().unwrap_or(&empty) {
+            let start = t;
+            t += (length(&mora["consonant_length"]) + length(&mora["vowel_length"])) / speed;
+            moras.push(Mora {
+                text: mora["text"].as_str().unwrap_or_default().to_string(),
+                start_sec: start,
+                end_sec: t,
+            });
         }
-        if buf.len() + sentence.len() > size {
-            res.push(buf.clone());
-            buf.clear();
+        if !phrase["pause_mora"].is_null() {
+            let pause = match query["pauseLength"].as_f64() {
+                Some(pause) => pause,
+                None => length(&phrase["pause_mora"]["vowel_length"]),
+            };
+            t += pause * pause_scale / speed;
         }
-        buf.push_str(sentence);
     }
-    if !buf.is_empty() {
-        res.push(buf.clone());
-        buf.clear();
-    }
-    res
+    moras
 }
 
 #[async_trait]
-impl AudioGenerator for VoiceVoxClient {
-    #[instrument(skip(self), ret)]
-    async fn generate(
-        &self,
-        i: &mut usize,
-        work_dir: &WorkDir,
-        section: Section,
-    ) -> Result<Vec<(PathBuf, String)>> {
-        let mut path_and_texts = vec![];
-
-        let Section::Serif { text, speaker } = section else {
-            return Err(anyhow::anyhow!("Invalid segment"));
-        };
-
-        for sentence in split_text(&text, 100) {
-            let wav_path = work_dir.dir().join(format!("{}.wav", i));
-            tracing::info!("[{}]: {}", i, sentence);
-            *i += 1;
-            if wav_path.exists() {
-                path_and_texts.push((wav_path, sentence));
-                continue;
-            }
+impl TtsEngine for VoiceVoxClient {
+    async fn version(&self) -> Result<String> {
+        let version = self.version().await?;
+        Ok(version.as_str().unwrap_or_default().to_string())
+    }
 
-            let query = self.query(&sentence, &speaker).await?;
-            let audio = self.synthesis(query, &speaker).await?;
+    async fn synthesize(
+        &self,
+        text: &str,
+        speaker: &str,
+        params: &VoiceParams,
+    ) -> Result<Synthesis> {
+        let mut query = self.query(text, speaker).await?;
+        merge_params(&mut query, params)?;
+        let moras = mora_timing(&query);
+        let wav = self.synthesis(query, speaker).await?;
+        Ok(Synthesis { wav, moras })
+    }
+}
 
-            let mut f = OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .read(true)
-                .open(&wav_path)?;
-            f.write_all(&audio)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-            let file = Box::new(f);
-            let mut r = Wav::<i16>::new(file)?;
-            if r.channels().count() == 1 {
-                let tmp = work_dir.dir().join("tmp.wav");
-                convert_to_stereo_wav(&wav_path, &tmp)?;
-                fs::rename(tmp, &wav_path)?;
-            }
-            path_and_texts.push((wav_path, sentence));
-        }
+    #[test]
+    fn test_merge_params() -> Result<()> {
+        let mut query = json!({ "accent_phrases": [], "speedScale": 1.0, "pitchScale": 0.0 });
+        let params = VoiceParams {
+            speed_scale: Some(1.5),
+            ..Default::default()
+        };
+        merge_params(&mut query, &params)?;
+        assert_eq!(
+            query,
+            json!({ "accent_phrases": [], "speedScale": 1.5, "pitchScale": 0.0 })
+        );
+        Ok(())
+    }
 
-        Ok(path_and_texts)
+    #[test]
+    fn test_mora_timing() {
+        let query = json!({
+            "accent_phrases": [
+                {
+                    "moras": [
+                        { "text": "コ", "consonant": "k", "consonant_length": 0.5, "vowel": "o", "vowel_length": 1.0 },
+                        { "text": "ン", "consonant": null, "consonant_length": null, "vowel": "N", "vowel_length": 0.5 },
+                    ],
+                    "pause_mora": { "text": "、", "vowel": "pau", "vowel_length": 1.0 },
+                },
+                {
+                    "moras": [
+                        { "text": "ワ", "consonant": "w", "consonant_length": 0.5, "vowel": "a", "vowel_length": 0.5 },
+                    ],
+                    "pause_mora": null,
+                },
+            ],
+            "speedScale": 2.0,
+            "prePhonemeLength": 1.0,
+            "postPhonemeLength": 1.0,
+        });
+        assert_eq!(
+            mora_timing(&query)
+                .into_iter()
+                .map(|mora| (mora.text, mora.start_sec, mora.end_sec))
+                .collect::<Vec<_>>(),
+            vec![
+                ("コ".to_string(), 0.5, 1.25),
+                ("ン".to_string(), 1.25, 1.5),
+                ("ワ".to_string(), 2.0, 2.5),
+            ]
+        );
     }
 }
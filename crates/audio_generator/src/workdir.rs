@@ -15,8 +15,9 @@ pub struct WorkDir {
 impl WorkDir {
     pub fn new(task_id: &Uuid, keep: bool) -> anyhow::Result<Self> {
         let task_id = task_id.hyphenated().to_string();
+        // NOTE: tasks run concurrently, so a kept dir is also per task
         let dir = if keep {
-            PathBuf::from("temp").join("workdir")
+            PathBuf::from("temp").join("workdir").join(&task_id)
         } else {
             PathBuf::from("temp").join(&task_id)
         };
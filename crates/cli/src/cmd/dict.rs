@@ -0,0 +1,90 @@
+use crate::credential::Credential;
+use anyhow::Result;
+use reqwest::{header::AUTHORIZATION, Response};
+use serde_json::{json, Value};
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct DictArgs {
+    #[clap(subcommand)]
+    cmd: DictCmd,
+}
+
+/// manages readings of words applied before synthesis
+#[derive(Debug, clap::Parser)]
+enum DictCmd {
+    /// lists all entries, or entries effective for the podcast
+    List {
+        #[clap(long)]
+        podcast_id: Option<String>,
+    },
+    /// adds or replaces the reading of a word
+    Add {
+        surface: String,
+        /// katakana
+        reading: String,
+        /// applies to all podcasts if omitted
+        #[clap(long)]
+        podcast_id: Option<String>,
+    },
+    Remove {
+        id: String,
+    },
+}
+
+async fn json_or_bail(res: Response) -> Result<Value> {
+    if !res.status().is_success() {
+        anyhow::bail!("{} {}", res.status(), res.text().await?);
+    }
+    Ok(res.json().await?)
+}
+
+pub(crate) async fn cmd_dict(credential: &Credential, args: DictArgs) -> Result<()> {
+    let client = reqwest::Client::new();
+    let url = format!("{}/pronunciations", credential.worker_endpoint);
+    match args.cmd {
+        DictCmd::List { podcast_id } => {
+            let mut req = client.get(&url).header(AUTHORIZATION, &credential.token);
+            if let Some(podcast_id) = podcast_id {
+                req = req.query(&[("podcastId", podcast_id)]);
+            }
+            let entries = json_or_bail(req.send().await?).await?;
+            for entry in entries.as_array().into_iter().flatten() {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    entry["id"].as_str().unwrap_or_default(),
+                    entry["surface"].as_str().unwrap_or_default(),
+                    entry["reading"].as_str().unwrap_or_default(),
+                    entry["podcast_id"].as_str().unwrap_or("*"),
+                );
+            }
+        }
+        DictCmd::Add {
+            surface,
+            reading,
+            podcast_id,
+        } => {
+            let req = client
+                .post(&url)
+                .header(AUTHORIZATION, &credential.token)
+                .json(&json!({
+                    "surface": surface,
+                    "reading": reading,
+                    "podcastId": podcast_id,
+                }));
+            let entry = json_or_bail(req.send().await?).await?;
+            println!("{}", serde_json::to_string_pretty(&entry)?);
+        }
+        DictCmd::Remove { id } => {
+            let res = client
+                .delete(format!("{}/{}", url, id))
+                .header(AUTHORIZATION, &credential.token)
+                .send()
+                .await?;
+            if !res.status().is_success() {
+                anyhow::bail!("{} {}", res.status(), res.text().await?);
+            }
+            println!("removed {}", id);
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use add::AddArgs;
+use dict::DictArgs;
 use list::ListArgs;
 use login::LoginArgs;
 use new::NewArgs;
@@ -8,6 +9,7 @@ use run::RunArgs;
 use std::path::PathBuf;
 
 pub(crate) mod add;
+pub(crate) mod dict;
 pub(crate) mod list;
 pub(crate) mod login;
 pub(crate) mod new;
@@ -32,4 +34,5 @@ pub(crate) enum Cmd {
     Push(PushArgs),
     Add(AddArgs),
     Run(RunArgs),
+    Dict(DictArgs),
 }
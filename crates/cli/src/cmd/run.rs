@@ -3,6 +3,7 @@ use anyhow::Result;
 use api::client::ApiClient;
 use script_runtime::{plugins::botcast_api::BotCastApiPlugin, runtime::ScriptRuntime};
 use std::{fs::File, path::PathBuf, sync::Arc};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, clap::Parser)]
 pub(crate) struct RunArgs {
@@ -16,7 +17,8 @@ pub(crate) async fn cmd_run(client: ApiClient, _project: Project, args: RunArgs)
     let context = serde_json::from_str(&args.context)?;
     let mut runtime = ScriptRuntime::default();
     runtime.install_plugin(BotCastApiPlugin::new(Arc::new(client)));
-    let result = runtime.run(&template, context).await?;
+    let cancel = CancellationToken::new();
+    let result = runtime.run(&template, context, &cancel).await?;
     println!("{}", serde_json::to_string_pretty(&result)?);
     Ok(())
 }
@@ -32,6 +32,7 @@ async fn main() -> Result<()> {
         Cmd::Push(args) => cmd::push::cmd_push(client, project, args).await?,
         Cmd::Add(args) => cmd::add::cmd_add(client, project, args).await?,
         Cmd::Run(args) => cmd::run::cmd_run(client, project, args).await?,
+        Cmd::Dict(args) => cmd::dict::cmd_dict(&credential, args).await?,
         Cmd::Login(_) => (),
     };
     Ok(())
@@ -0,0 +1,5 @@
+alter table tasks add column worker_id text;
+alter table tasks add column lease_expires_at timestamptz;
+
+create index tasks_pending_idx on tasks (execute_after) where status = 'PENDING';
+create index tasks_running_lease_idx on tasks (lease_expires_at) where status = 'RUNNING';
@@ -0,0 +1,4 @@
+alter table tasks add column attempts integer not null default 0;
+alter table tasks add column retry_policy jsonb;
+-- `result` keeps the value of the task, failed attempts are kept aside
+alter table tasks add column errors jsonb not null default '[]';
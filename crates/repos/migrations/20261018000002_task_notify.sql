@@ -0,0 +1,11 @@
+create or replace function notify_task_pending() returns trigger as $$
+begin
+    perform pg_notify('tasks_pending', new.id::text);
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger tasks_pending_notify
+    after insert or update of status on tasks
+    for each row when (new.status = 'PENDING')
+    execute function notify_task_pending();
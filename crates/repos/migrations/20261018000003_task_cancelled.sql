@@ -0,0 +1 @@
+alter type task_status add value if not exists 'CANCELLED';
@@ -0,0 +1 @@
+alter table tasks add column timeout_sec integer;
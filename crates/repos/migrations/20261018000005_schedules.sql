@@ -0,0 +1,38 @@
+create type missed_fire_policy as enum ('SKIP', 'CATCH_UP');
+
+create table schedules (
+    id uuid primary key,
+    user_id uuid,
+    cron text not null,
+    args jsonb not null,
+    retry_policy jsonb,
+    timeout_sec integer,
+    enabled boolean not null default true,
+    missed_fire_policy missed_fire_policy not null default 'SKIP',
+    last_fire_at timestamptz,
+    next_fire_at timestamptz not null,
+    created_at timestamptz not null default now()
+);
+create index schedules_due on schedules (next_fire_at) where enabled;
+
+alter table tasks add column schedule_id uuid references schedules (id) on delete set null;
+alter table tasks add column fire_time timestamptz;
+-- a fire of a schedule is materialized only once
+create unique index tasks_schedule_fire on tasks (schedule_id, fire_time);
+
+-- self-replicating cron tasks become schedules.
+-- pending tasks are kept as cancelled with a link to their schedule, instead of being deleted.
+-- running tasks keep running as the fire of their schedule at `execute_after`,
+-- so the schedule does not materialize that fire again and continues the chain after it
+with migrated as (
+    select id as task_id, status, gen_random_uuid() as schedule_id, user_id, cron, args, retry_policy, timeout_sec, execute_after
+    from tasks where cron is not null and status in ('PENDING', 'RUNNING')
+), created as (
+    insert into schedules (id, user_id, cron, args, retry_policy, timeout_sec, next_fire_at)
+    select schedule_id, user_id, cron, args, retry_policy, timeout_sec, execute_after from migrated
+)
+update tasks set
+    status = case when migrated.status = 'PENDING' then 'CANCELLED'::task_status else tasks.status end,
+    schedule_id = migrated.schedule_id,
+    fire_time = case when migrated.status = 'RUNNING' then migrated.execute_after end
+from migrated where tasks.id = migrated.task_id;
@@ -0,0 +1,3 @@
+alter table schedules add column timezone text not null default 'UTC';
+alter table tasks add column timezone text;
+update tasks set timezone = schedules.timezone from schedules where tasks.schedule_id = schedules.id;
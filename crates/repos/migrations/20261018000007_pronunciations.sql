@@ -0,0 +1,15 @@
+-- readings of words which tts engines read badly, per user or per podcast
+create table pronunciations (
+    id uuid primary key,
+    user_id uuid not null,
+    podcast_id uuid,
+    surface text not null,
+    reading text not null,
+    created_at timestamptz not null default now()
+);
+-- a surface has one reading in the scope of a user or a podcast
+create unique index pronunciations_surface on pronunciations (
+    user_id,
+    coalesce(podcast_id, '00000000-0000-0000-0000-000000000000'),
+    surface
+);
@@ -0,0 +1,2 @@
+-- facts about the generated audio, e.g. loudness
+alter table episodes add column metadata jsonb not null default '{}'::jsonb;
@@ -0,0 +1,2 @@
+-- encodings of episodes, the first one is the primary audio
+alter table podcasts add column output_profiles jsonb;
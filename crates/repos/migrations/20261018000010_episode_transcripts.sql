@@ -0,0 +1,4 @@
+-- subtitles next to srt_url
+alter table episodes add column vtt_url text;
+-- json of section, sentence and mora timings
+alter table episodes add column transcript_url text;
@@ -0,0 +1,2 @@
+-- results of completed steps of a pipeline, later steps take them and a retry resumes after them
+alter table tasks add column step_results jsonb not null default '[]';
@@ -26,6 +26,14 @@ pub struct MailId(pub Uuid);
 #[serde(transparent)]
 pub struct TaskId(pub Uuid);
 
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct ScheduleId(pub Uuid);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct PronunciationId(pub Uuid);
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
 pub struct Podcast {
     pub id: Uuid,
@@ -34,6 +42,9 @@ pub struct Podcast {
     pub icon: String,
     pub user_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
+    /// `OutputProfile`s of episodes, mp3 only if `None`
+    #[serde(default)]
+    pub output_profiles: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
@@ -48,6 +59,13 @@ pub struct Episode {
     pub podcast_id: Uuid,
     pub user_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
+    /// facts about the generated audio, e.g. `loudness`
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+    #[serde(default)]
+    pub vtt_url: Option<String>,
+    #[serde(default)]
+    pub transcript_url: Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
@@ -80,17 +98,19 @@ pub struct Mail {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
 #[sqlx(type_name = "task_status", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
 pub enum TaskStatus {
     None,
     Pending,
     Running,
     Completed,
     Failed,
+    Cancelled,
 }
 
-#[derive(Debug, serde::Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
 pub struct Task {
     pub id: Uuid,
     pub user_id: Option<Uuid>,
@@ -101,6 +121,64 @@ pub struct Task {
     pub execute_after: DateTime<Utc>,
     pub executed_at: Option<DateTime<Utc>>,
     pub executed_finished_at: Option<DateTime<Utc>>,
+    /// worker which claimed this task
+    pub worker_id: Option<String>,
+    /// the claim is released when the worker stops renewing it
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    /// number of finished attempts
+    pub attempts: i32,
+    pub retry_policy: Option<serde_json::Value>,
+    /// overrides the default timeout of the task type
+    pub timeout_sec: Option<i32>,
+    /// schedule which materialized this task
+    pub schedule_id: Option<Uuid>,
+    pub fire_time: Option<DateTime<Utc>>,
+    /// IANA timezone of the schedule
+    pub timezone: Option<String>,
+    /// errors of failed attempts
+    pub errors: serde_json::Value,
+    /// results of completed steps of a pipeline
+    pub step_results: serde_json::Value,
+}
+
+/// how fires missed while no worker was running are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
+#[sqlx(type_name = "missed_fire_policy", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MissedFirePolicy {
+    /// drops fires older than a grace period
+    Skip,
+    /// materializes every missed fire
+    CatchUp,
+}
+
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct Schedule {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub cron: String,
+    /// IANA timezone name
+    pub timezone: String,
+    pub args: serde_json::Value,
+    pub retry_policy: Option<serde_json::Value>,
+    pub timeout_sec: Option<i32>,
+    pub enabled: bool,
+    pub missed_fire_policy: MissedFirePolicy,
+    pub last_fire_at: Option<DateTime<Utc>>,
+    pub next_fire_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// reading of a word, scoped to a podcast or to all podcasts of the user
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct Pronunciation {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub podcast_id: Option<Uuid>,
+    pub surface: String,
+    /// katakana
+    pub reading: String,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, serde::Serialize, sqlx::FromRow)]
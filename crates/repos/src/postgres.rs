@@ -1,10 +1,14 @@
 use crate::{
     entity::{
-        Corner, CornerId, Episode, EpisodeId, Mail, MailId, Podcast, PodcastId, Script, ScriptId,
-        Secret, Task, TaskId, TaskStatus,
+        Corner, CornerId, Episode, EpisodeId, Mail, MailId, MissedFirePolicy, Podcast, PodcastId,
+        Pronunciation, PronunciationId, Schedule, ScheduleId, Script, ScriptId, Secret, Task,
+        TaskId, TaskStatus,
     },
     error::Error,
-    repo::{CornerRepo, EpisodeRepo, MailRepo, PodcastRepo, ScriptRepo, SecretRepo, TaskRepo},
+    repo::{
+        CornerRepo, EpisodeRepo, MailRepo, PodcastRepo, PronunciationRepo, ScheduleRepo,
+        ScriptRepo, SecretRepo, TaskRepo, TaskTypeFilter,
+    },
 };
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -17,6 +21,16 @@ pub static PG_POOL: LazyLock<Pool<Postgres>> = LazyLock::new(|| {
     PgPool::connect_lazy(&database_url).expect("Failed to connect to DB")
 });
 
+/// applies `migrations` of the worker's tables and columns, on startup if `MIGRATE_ON_START`.
+/// the tables shared with the web app are created by its schema beforehand
+pub async fn migrate() -> anyhow::Result<()> {
+    sqlx::migrate!("./migrations").run(&*PG_POOL).await?;
+    Ok(())
+}
+
+/// notified with the task id when a task becomes pending
+pub const TASKS_PENDING_CHANNEL: &str = "tasks_pending";
+
 pub struct PostgresPodcastRepo {
     pool: Pool<Postgres>,
 }
@@ -111,7 +125,7 @@ impl EpisodeRepo for PostgresEpisodeRepo {
     async fn create(&self, episode: &Episode) -> anyhow::Result<(), Error> {
         sqlx::query_as!(
             Episode,
-            "insert into episodes (id, user_id, title, description, podcast_id, sections, audio_url, duration_sec, srt_url) values ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            "insert into episodes (id, user_id, title, description, podcast_id, sections, audio_url, duration_sec, srt_url, metadata, vtt_url, transcript_url) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
             episode.id,
             episode.user_id,
             episode.title,
@@ -121,6 +135,9 @@ impl EpisodeRepo for PostgresEpisodeRepo {
             episode.audio_url,
             episode.duration_sec,
             episode.srt_url,
+            episode.metadata,
+            episode.vtt_url,
+            episode.transcript_url,
         )
         .execute(&self.pool)
         .await
@@ -131,13 +148,16 @@ impl EpisodeRepo for PostgresEpisodeRepo {
     async fn update(&self, episode: &Episode) -> anyhow::Result<(), Error> {
         sqlx::query_as!(
             Episode,
-            "update episodes set title = $2, description = $3, audio_url = $4, duration_sec = $5, srt_url = $6 where id = $1",
+            "update episodes set title = $2, description = $3, audio_url = $4, duration_sec = $5, srt_url = $6, metadata = $7, vtt_url = $8, transcript_url = $9 where id = $1",
             episode.id,
             episode.title,
             episode.description,
             episode.audio_url,
             episode.duration_sec,
             episode.srt_url,
+            episode.metadata,
+            episode.vtt_url,
+            episode.transcript_url,
         )
         .execute(&self.pool)
         .await
@@ -327,12 +347,65 @@ impl PostgresTaskRepo {
 
 #[async_trait]
 impl TaskRepo for PostgresTaskRepo {
-    async fn pop(&self, now: DateTime<Utc>) -> anyhow::Result<Option<Task>, Error> {
+    async fn find_by_id(&self, id: &TaskId) -> anyhow::Result<Task, Error> {
+        let Some(task) = sqlx::query_as!(
+            Task,
+            r#"select id, user_id, status as "status!: TaskStatus", cron, args, result, execute_after, executed_at, executed_finished_at, worker_id, lease_expires_at, attempts, retry_policy, timeout_sec, schedule_id, fire_time, timezone, errors, step_results from tasks where id = $1"#,
+            id.0
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::Other)?
+        else {
+            return Err(Error::NotFound("task".to_string(), id.0.to_string()));
+        };
+        Ok(task)
+    }
+
+    async fn find_by_user(
+        &self,
+        user_id: &Uuid,
+        status: Option<TaskStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> anyhow::Result<Vec<Task>, Error> {
+        let tasks = sqlx::query_as!(
+            Task,
+            r#"select id, user_id, status as "status!: TaskStatus", cron, args, result, execute_after, executed_at, executed_finished_at, worker_id, lease_expires_at, attempts, retry_policy, timeout_sec, schedule_id, fire_time, timezone, errors, step_results from tasks where user_id = $1 and ($2::task_status is null or status = $2) order by execute_after desc limit $3 offset $4"#,
+            user_id,
+            status as Option<TaskStatus>,
+            limit,
+            offset,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(tasks)
+    }
+
+    async fn pop(
+        &self,
+        worker_id: &str,
+        now: DateTime<Utc>,
+        lease_expires_at: DateTime<Utc>,
+        filter: &TaskTypeFilter,
+    ) -> anyhow::Result<Option<Task>, Error> {
+        let (types, include) = match filter {
+            TaskTypeFilter::Any => (None, true),
+            TaskTypeFilter::Only(types) => (Some(types.as_slice()), true),
+            TaskTypeFilter::Except(types) => (Some(types.as_slice()), false),
+        };
+        // NOTE: `for update skip locked` lets concurrent workers claim different tasks
         let task = sqlx::query_as!(
             Task,
-            r#"select id, user_id, status as "status!: TaskStatus", cron, args, result, execute_after, executed_at, executed_finished_at from tasks where status = $1 and execute_after < $2 order by execute_after limit 1"#,
-            TaskStatus::Pending as TaskStatus,
+            r#"update tasks set status = $1, worker_id = $2, executed_at = $3, lease_expires_at = $4 where id = (select id from tasks where status = $5 and execute_after < $3 and ($6::text[] is null or (args->>'type' = any($6) or jsonb_path_query_array(args, '$.steps[*].type') ?| $6) = $7) order by execute_after limit 1 for update skip locked) returning id, user_id, status as "status!: TaskStatus", cron, args, result, execute_after, executed_at, executed_finished_at, worker_id, lease_expires_at, attempts, retry_policy, timeout_sec, schedule_id, fire_time, timezone, errors, step_results"#,
+            TaskStatus::Running as TaskStatus,
+            worker_id,
             now,
+            lease_expires_at,
+            TaskStatus::Pending as TaskStatus,
+            types,
+            include,
         )
         .fetch_optional(&self.pool)
         .await
@@ -340,10 +413,55 @@ impl TaskRepo for PostgresTaskRepo {
         Ok(task)
     }
 
+    async fn renew_lease(
+        &self,
+        id: &TaskId,
+        worker_id: &str,
+        lease_expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool, Error> {
+        let res = sqlx::query!(
+            "update tasks set lease_expires_at = $3 where id = $1 and worker_id = $2 and status = $4",
+            id.0,
+            worker_id,
+            lease_expires_at,
+            TaskStatus::Running as TaskStatus,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn reap_expired(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TaskId>, Error> {
+        let ids = sqlx::query_scalar!(
+            "update tasks set status = $1, worker_id = null, lease_expires_at = null where status = $2 and lease_expires_at < $3 returning id",
+            TaskStatus::Pending as TaskStatus,
+            TaskStatus::Running as TaskStatus,
+            now,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(ids.into_iter().map(TaskId).collect())
+    }
+
+    async fn release(&self, worker_id: &str) -> anyhow::Result<Vec<TaskId>, Error> {
+        let ids = sqlx::query_scalar!(
+            "update tasks set status = $1, worker_id = null, lease_expires_at = null where status = $2 and worker_id = $3 returning id",
+            TaskStatus::Pending as TaskStatus,
+            TaskStatus::Running as TaskStatus,
+            worker_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(ids.into_iter().map(TaskId).collect())
+    }
+
     async fn create(&self, task: &Task) -> anyhow::Result<(), Error> {
         sqlx::query_as!(
             Task,
-            "insert into tasks (id, user_id, status, cron, args, result, execute_after, executed_at, executed_finished_at) values ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            "insert into tasks (id, user_id, status, cron, args, result, execute_after, executed_at, executed_finished_at, worker_id, lease_expires_at, attempts, retry_policy, timeout_sec, schedule_id, fire_time, timezone, errors, step_results) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
             task.id,
             task.user_id,
             &task.status as &TaskStatus,
@@ -353,6 +471,16 @@ impl TaskRepo for PostgresTaskRepo {
             task.execute_after,
             task.executed_at,
             task.executed_finished_at,
+            task.worker_id,
+            task.lease_expires_at,
+            task.attempts,
+            task.retry_policy,
+            task.timeout_sec,
+            task.schedule_id,
+            task.fire_time,
+            task.timezone,
+            task.errors,
+            task.step_results,
         )
         .execute(&self.pool)
         .await
@@ -360,10 +488,39 @@ impl TaskRepo for PostgresTaskRepo {
         Ok(())
     }
 
+    async fn create_scheduled(&self, task: &Task) -> anyhow::Result<bool, Error> {
+        let res = sqlx::query!(
+            "insert into tasks (id, user_id, status, cron, args, result, execute_after, executed_at, executed_finished_at, worker_id, lease_expires_at, attempts, retry_policy, timeout_sec, schedule_id, fire_time, timezone, errors, step_results) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) on conflict (schedule_id, fire_time) do nothing",
+            task.id,
+            task.user_id,
+            &task.status as &TaskStatus,
+            task.cron,
+            task.args,
+            task.result,
+            task.execute_after,
+            task.executed_at,
+            task.executed_finished_at,
+            task.worker_id,
+            task.lease_expires_at,
+            task.attempts,
+            task.retry_policy,
+            task.timeout_sec,
+            task.schedule_id,
+            task.fire_time,
+            task.timezone,
+            task.errors,
+            task.step_results,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(res.rows_affected() == 1)
+    }
+
     async fn update(&self, task: &Task) -> anyhow::Result<(), Error> {
         sqlx::query_as!(
             Task,
-            "update tasks set status = $2, args = $3, result = $4, execute_after = $5, executed_at = $6, executed_finished_at = $7 where id = $1",
+            "update tasks set status = $2, args = $3, result = $4, execute_after = $5, executed_at = $6, executed_finished_at = $7, worker_id = $8, lease_expires_at = $9, attempts = $10, errors = $11, step_results = $12 where id = $1",
             task.id,
             &task.status as &TaskStatus,
             task.args,
@@ -371,6 +528,11 @@ impl TaskRepo for PostgresTaskRepo {
             task.execute_after,
             task.executed_at,
             task.executed_finished_at,
+            task.worker_id,
+            task.lease_expires_at,
+            task.attempts,
+            task.errors,
+            task.step_results,
         )
         .execute(&self.pool)
         .await
@@ -378,6 +540,59 @@ impl TaskRepo for PostgresTaskRepo {
         Ok(())
     }
 
+    async fn update_claimed(&self, task: &Task, worker_id: &str) -> anyhow::Result<bool, Error> {
+        let res = sqlx::query!(
+            "update tasks set status = $2, args = $3, result = $4, execute_after = $5, executed_at = $6, executed_finished_at = $7, worker_id = $8, lease_expires_at = $9, attempts = $10, errors = $11, step_results = $12 where id = $1 and status = $13 and worker_id = $14",
+            task.id,
+            &task.status as &TaskStatus,
+            task.args,
+            task.result,
+            task.execute_after,
+            task.executed_at,
+            task.executed_finished_at,
+            task.worker_id,
+            task.lease_expires_at,
+            task.attempts,
+            task.errors,
+            task.step_results,
+            TaskStatus::Running as TaskStatus,
+            worker_id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn cancel(&self, id: &TaskId, now: DateTime<Utc>) -> anyhow::Result<bool, Error> {
+        let res = sqlx::query!(
+            "update tasks set status = $2, executed_finished_at = $3, worker_id = null, lease_expires_at = null where id = $1 and status in ($4, $5)",
+            id.0,
+            TaskStatus::Cancelled as TaskStatus,
+            now,
+            TaskStatus::Pending as TaskStatus,
+            TaskStatus::Running as TaskStatus,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn requeue(&self, id: &TaskId, now: DateTime<Utc>) -> anyhow::Result<bool, Error> {
+        let res = sqlx::query!(
+            "update tasks set status = $2, result = null, attempts = 0, errors = '[]', step_results = '[]', execute_after = $3, executed_at = null, executed_finished_at = null where id = $1 and status = $4",
+            id.0,
+            TaskStatus::Pending as TaskStatus,
+            now,
+            TaskStatus::Failed as TaskStatus,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(res.rows_affected() == 1)
+    }
+
     async fn delete(&self, id: &TaskId) -> anyhow::Result<(), Error> {
         sqlx::query("delete from tasks where id = $1")
             .bind(id.0)
@@ -388,6 +603,136 @@ impl TaskRepo for PostgresTaskRepo {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct PostgresScheduleRepo {
+    pool: Pool<Postgres>,
+}
+
+impl Default for PostgresScheduleRepo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostgresScheduleRepo {
+    pub fn new() -> Self {
+        let pool = PG_POOL.clone();
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ScheduleRepo for PostgresScheduleRepo {
+    async fn find_by_id(&self, id: &ScheduleId) -> anyhow::Result<Schedule, Error> {
+        let Some(schedule) = sqlx::query_as!(
+            Schedule,
+            r#"select id, user_id, cron, timezone, args, retry_policy, timeout_sec, enabled, missed_fire_policy as "missed_fire_policy!: MissedFirePolicy", last_fire_at, next_fire_at, created_at from schedules where id = $1"#,
+            id.0
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::Other)?
+        else {
+            return Err(Error::NotFound("schedule".to_string(), id.0.to_string()));
+        };
+        Ok(schedule)
+    }
+
+    async fn find_by_user(&self, user_id: &Uuid) -> anyhow::Result<Vec<Schedule>, Error> {
+        let schedules = sqlx::query_as!(
+            Schedule,
+            r#"select id, user_id, cron, timezone, args, retry_policy, timeout_sec, enabled, missed_fire_policy as "missed_fire_policy!: MissedFirePolicy", last_fire_at, next_fire_at, created_at from schedules where user_id = $1 order by created_at"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(schedules)
+    }
+
+    async fn find_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Schedule>, Error> {
+        let schedules = sqlx::query_as!(
+            Schedule,
+            r#"select id, user_id, cron, timezone, args, retry_policy, timeout_sec, enabled, missed_fire_policy as "missed_fire_policy!: MissedFirePolicy", last_fire_at, next_fire_at, created_at from schedules where enabled and next_fire_at <= $1 order by next_fire_at"#,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(schedules)
+    }
+
+    async fn create(&self, schedule: &Schedule) -> anyhow::Result<(), Error> {
+        sqlx::query!(
+            "insert into schedules (id, user_id, cron, timezone, args, retry_policy, timeout_sec, enabled, missed_fire_policy, last_fire_at, next_fire_at, created_at) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+            schedule.id,
+            schedule.user_id,
+            schedule.cron,
+            schedule.timezone,
+            schedule.args,
+            schedule.retry_policy,
+            schedule.timeout_sec,
+            schedule.enabled,
+            schedule.missed_fire_policy as MissedFirePolicy,
+            schedule.last_fire_at,
+            schedule.next_fire_at,
+            schedule.created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(())
+    }
+
+    async fn update(&self, schedule: &Schedule) -> anyhow::Result<(), Error> {
+        sqlx::query!(
+            "update schedules set cron = $2, timezone = $3, args = $4, retry_policy = $5, timeout_sec = $6, enabled = $7, missed_fire_policy = $8, last_fire_at = $9, next_fire_at = $10 where id = $1",
+            schedule.id,
+            schedule.cron,
+            schedule.timezone,
+            schedule.args,
+            schedule.retry_policy,
+            schedule.timeout_sec,
+            schedule.enabled,
+            schedule.missed_fire_policy as MissedFirePolicy,
+            schedule.last_fire_at,
+            schedule.next_fire_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(())
+    }
+
+    async fn advance(
+        &self,
+        id: &ScheduleId,
+        expected_next_fire_at: DateTime<Utc>,
+        last_fire_at: Option<DateTime<Utc>>,
+        next_fire_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool, Error> {
+        let res = sqlx::query!(
+            "update schedules set last_fire_at = coalesce($3, last_fire_at), next_fire_at = $4 where id = $1 and next_fire_at = $2",
+            id.0,
+            expected_next_fire_at,
+            last_fire_at,
+            next_fire_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn delete(&self, id: &ScheduleId) -> anyhow::Result<(), Error> {
+        sqlx::query!("delete from schedules where id = $1", id.0)
+            .execute(&self.pool)
+            .await
+            .map_err(Error::Other)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PostgresSecretRepo {
     pool: Pool<Postgres>,
@@ -427,3 +772,95 @@ impl SecretRepo for PostgresSecretRepo {
         })
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct PostgresPronunciationRepo {
+    pool: Pool<Postgres>,
+}
+
+impl Default for PostgresPronunciationRepo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostgresPronunciationRepo {
+    pub fn new() -> Self {
+        let pool = PG_POOL.clone();
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl PronunciationRepo for PostgresPronunciationRepo {
+    async fn find_by_id(&self, id: &PronunciationId) -> anyhow::Result<Pronunciation, Error> {
+        let Some(pronunciation) = sqlx::query_as!(
+            Pronunciation,
+            "select id, user_id, podcast_id, surface, reading, created_at from pronunciations where id = $1",
+            id.0
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::Other)?
+        else {
+            return Err(Error::NotFound("pronunciation".to_string(), id.0.to_string()));
+        };
+        Ok(pronunciation)
+    }
+
+    async fn find_by_user(&self, user_id: &Uuid) -> anyhow::Result<Vec<Pronunciation>, Error> {
+        let pronunciations = sqlx::query_as!(
+            Pronunciation,
+            "select id, user_id, podcast_id, surface, reading, created_at from pronunciations where user_id = $1 order by surface",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(pronunciations)
+    }
+
+    async fn find_for_podcast(
+        &self,
+        user_id: &Uuid,
+        podcast_id: &PodcastId,
+    ) -> anyhow::Result<Vec<Pronunciation>, Error> {
+        let pronunciations = sqlx::query_as!(
+            Pronunciation,
+            "select id, user_id, podcast_id, surface, reading, created_at from pronunciations where user_id = $1 and (podcast_id is null or podcast_id = $2) order by podcast_id nulls first, surface",
+            user_id,
+            podcast_id.0
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(pronunciations)
+    }
+
+    async fn upsert(&self, pronunciation: &Pronunciation) -> anyhow::Result<Pronunciation, Error> {
+        let pronunciation = sqlx::query_as!(
+            Pronunciation,
+            "insert into pronunciations (id, user_id, podcast_id, surface, reading, created_at) values ($1, $2, $3, $4, $5, $6)
+            on conflict (user_id, coalesce(podcast_id, '00000000-0000-0000-0000-000000000000'), surface) do update set reading = excluded.reading
+            returning id, user_id, podcast_id, surface, reading, created_at",
+            pronunciation.id,
+            pronunciation.user_id,
+            pronunciation.podcast_id,
+            pronunciation.surface,
+            pronunciation.reading,
+            pronunciation.created_at,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Error::Other)?;
+        Ok(pronunciation)
+    }
+
+    async fn delete(&self, id: &PronunciationId) -> anyhow::Result<(), Error> {
+        sqlx::query!("delete from pronunciations where id = $1", id.0)
+            .execute(&self.pool)
+            .await
+            .map_err(Error::Other)?;
+        Ok(())
+    }
+}
@@ -25,6 +25,14 @@ pub trait ProvideTaskRepo: Debug + Send + Sync {
     fn task_repo(&self) -> Arc<dyn TaskRepo>;
 }
 
+pub trait ProvideScheduleRepo: Debug + Send + Sync {
+    fn schedule_repo(&self) -> Arc<dyn ScheduleRepo>;
+}
+
+pub trait ProvidePronunciationRepo: Debug + Send + Sync {
+    fn pronunciation_repo(&self) -> Arc<dyn PronunciationRepo>;
+}
+
 pub trait ProvideSecretRepo: Debug + Send + Sync {
     fn secret_repo(&self) -> Arc<dyn SecretRepo>;
 }
@@ -68,6 +76,18 @@ impl ProvideTaskRepo for DefaultProvider {
     }
 }
 
+impl ProvideScheduleRepo for DefaultProvider {
+    fn schedule_repo(&self) -> Arc<dyn ScheduleRepo> {
+        Arc::new(PostgresScheduleRepo::new())
+    }
+}
+
+impl ProvidePronunciationRepo for DefaultProvider {
+    fn pronunciation_repo(&self) -> Arc<dyn PronunciationRepo> {
+        Arc::new(PostgresPronunciationRepo::new())
+    }
+}
+
 impl ProvideSecretRepo for DefaultProvider {
     fn secret_repo(&self) -> Arc<dyn SecretRepo> {
         Arc::new(PostgresSecretRepo::new())
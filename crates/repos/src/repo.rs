@@ -1,7 +1,7 @@
 use crate::{
     entity::{
-        Corner, CornerId, Episode, EpisodeId, Mail, MailId, Podcast, PodcastId, Script, ScriptId,
-        Secret, Task, TaskId,
+        Corner, CornerId, Episode, EpisodeId, Mail, MailId, Podcast, PodcastId, Pronunciation,
+        PronunciationId, Schedule, ScheduleId, Script, ScriptId, Secret, Task, TaskId, TaskStatus,
     },
     error::Error,
 };
@@ -44,15 +44,93 @@ pub trait MailRepo: Send + Sync {
     async fn update(&self, mail: &Mail) -> anyhow::Result<(), Error>;
 }
 
+/// selects tasks by `args.type`, pipelines also match by the types of their steps
+#[derive(Debug, Clone)]
+pub enum TaskTypeFilter {
+    Any,
+    Only(Vec<String>),
+    Except(Vec<String>),
+}
+
 #[async_trait]
 pub trait TaskRepo: Send + Sync {
-    async fn pop(&self, now: DateTime<Utc>) -> anyhow::Result<Option<Task>, Error>;
+    async fn find_by_id(&self, id: &TaskId) -> anyhow::Result<Task, Error>;
+    /// newest first
+    async fn find_by_user(
+        &self,
+        user_id: &Uuid,
+        status: Option<TaskStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> anyhow::Result<Vec<Task>, Error>;
+    /// claims the oldest pending task for `worker_id` until `lease_expires_at`
+    async fn pop(
+        &self,
+        worker_id: &str,
+        now: DateTime<Utc>,
+        lease_expires_at: DateTime<Utc>,
+        filter: &TaskTypeFilter,
+    ) -> anyhow::Result<Option<Task>, Error>;
+    /// returns `false` if the task is no longer claimed by `worker_id`
+    async fn renew_lease(
+        &self,
+        id: &TaskId,
+        worker_id: &str,
+        lease_expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool, Error>;
+    /// returns running tasks whose lease has expired to pending
+    async fn reap_expired(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<TaskId>, Error>;
+    /// returns running tasks claimed by `worker_id` to pending
+    async fn release(&self, worker_id: &str) -> anyhow::Result<Vec<TaskId>, Error>;
     async fn create(&self, task: &Task) -> anyhow::Result<(), Error>;
+    /// returns `false` if the fire of the schedule is already materialized
+    async fn create_scheduled(&self, task: &Task) -> anyhow::Result<bool, Error>;
     async fn update(&self, task: &Task) -> anyhow::Result<(), Error>;
+    /// same as `update`, but returns `false` if the task is no longer claimed by `worker_id`
+    async fn update_claimed(&self, task: &Task, worker_id: &str) -> anyhow::Result<bool, Error>;
+    /// cancels a pending or running task, returns `false` otherwise
+    async fn cancel(&self, id: &TaskId, now: DateTime<Utc>) -> anyhow::Result<bool, Error>;
+    /// re-queues a failed task, returns `false` otherwise
+    async fn requeue(&self, id: &TaskId, now: DateTime<Utc>) -> anyhow::Result<bool, Error>;
     #[allow(dead_code)]
     async fn delete(&self, id: &TaskId) -> anyhow::Result<(), Error>;
 }
 
+#[async_trait]
+pub trait ScheduleRepo: Send + Sync {
+    async fn find_by_id(&self, id: &ScheduleId) -> anyhow::Result<Schedule, Error>;
+    async fn find_by_user(&self, user_id: &Uuid) -> anyhow::Result<Vec<Schedule>, Error>;
+    /// enabled schedules whose next fire is not after `now`
+    async fn find_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Schedule>, Error>;
+    async fn create(&self, schedule: &Schedule) -> anyhow::Result<(), Error>;
+    async fn update(&self, schedule: &Schedule) -> anyhow::Result<(), Error>;
+    /// moves the next fire from `expected_next_fire_at`,
+    /// returns `false` if another worker has already moved it
+    async fn advance(
+        &self,
+        id: &ScheduleId,
+        expected_next_fire_at: DateTime<Utc>,
+        last_fire_at: Option<DateTime<Utc>>,
+        next_fire_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool, Error>;
+    async fn delete(&self, id: &ScheduleId) -> anyhow::Result<(), Error>;
+}
+
+#[async_trait]
+pub trait PronunciationRepo: Send + Sync {
+    async fn find_by_id(&self, id: &PronunciationId) -> anyhow::Result<Pronunciation, Error>;
+    async fn find_by_user(&self, user_id: &Uuid) -> anyhow::Result<Vec<Pronunciation>, Error>;
+    /// entries of the user for the podcast, podcast scoped ones come after user scoped ones
+    async fn find_for_podcast(
+        &self,
+        user_id: &Uuid,
+        podcast_id: &PodcastId,
+    ) -> anyhow::Result<Vec<Pronunciation>, Error>;
+    /// replaces the entry of the same surface in the same scope
+    async fn upsert(&self, pronunciation: &Pronunciation) -> anyhow::Result<Pronunciation, Error>;
+    async fn delete(&self, id: &PronunciationId) -> anyhow::Result<(), Error>;
+}
+
 #[async_trait]
 pub trait SecretRepo: Send + Sync {
     async fn find_by_name(&self, user_id: &Uuid, name: &str) -> anyhow::Result<Secret, Error>;
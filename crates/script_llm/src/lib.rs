@@ -12,6 +12,10 @@ use openai_api_rs::v1::{
 };
 use std::time::Duration;
 
+/// an assistant run is abandoned after this duration
+const RUN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const RUN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 fn create_client(open_ai_api_key: String) -> Result<OpenAIClient> {
     let client = OpenAIClient::builder()
         .with_api_key(open_ai_api_key)
@@ -124,14 +128,22 @@ pub async fn chat_assistant(
     let run = client
         .create_run(thread_id.clone(), CreateRunRequest::new(assistant_id))
         .await?;
+    let deadline = tokio::time::Instant::now() + RUN_TIMEOUT;
     loop {
         let res = client
             .retrieve_run(thread_id.clone(), run.id.clone())
             .await?;
-        if res.status == "completed" {
-            break;
+        match res.status.as_str() {
+            "completed" => break,
+            "failed" | "cancelled" | "expired" | "incomplete" | "requires_action" => {
+                anyhow::bail!("Run {} is {}", run.id, res.status)
+            }
+            _ => {}
+        }
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Run {} timed out after {:?}", run.id, RUN_TIMEOUT);
         }
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        tokio::time::sleep(RUN_POLL_INTERVAL).await;
     }
 
     let results = client.list_messages(thread_id).await?;
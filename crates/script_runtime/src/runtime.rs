@@ -2,6 +2,7 @@ use crate::plugins::{default_plugins, Plugin};
 use anyhow::Result;
 use json_e::{builtins::builtins, value::Value, Context};
 use std::collections::BTreeMap;
+use tokio_util::sync::CancellationToken;
 
 pub(crate) fn insert_values(context: &mut Context<'_>, values: BTreeMap<String, Value>) {
     for (k, v) in values {
@@ -35,17 +36,22 @@ impl ScriptRuntime<'_> {
         plugin.register_functions(&mut self.context);
     }
 
-    #[tracing::instrument(skip(self))]
+    /// evaluation is aborted when `cancel` is triggered
+    #[tracing::instrument(skip(self, cancel))]
     pub async fn run(
         &mut self,
         template: &serde_json::Value,
         values: BTreeMap<String, serde_json::Value>,
+        cancel: &CancellationToken,
     ) -> Result<serde_json::Value> {
         insert_values(
             &mut self.context,
             values.into_iter().map(|(k, v)| (k, v.into())).collect(),
         );
-        json_e::render_with_context(template, &self.context).await
+        tokio::select! {
+            res = json_e::render_with_context(template, &self.context) => res,
+            _ = cancel.cancelled() => anyhow::bail!("Cancelled while evaluating template"),
+        }
     }
 }
 
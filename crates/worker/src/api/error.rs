@@ -19,6 +19,7 @@ impl IntoResponse for Error {
             Error::UnAuthorized => {
                 (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()).into_response()
             }
+            Error::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, self.to_string()).into_response(),
             Error::Other(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
         }
     }
@@ -1,6 +1,6 @@
 use crate::usecase::provider::Provider;
 use router::routers;
-use std::sync::Arc;
+use std::{future::Future, path::Path, sync::Arc};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
@@ -10,14 +10,22 @@ mod router;
 #[derive(Debug)]
 struct AppState(Arc<Provider>);
 
-pub async fn start_api(provider: Arc<Provider>) -> anyhow::Result<()> {
+/// serves until `shutdown` completes and in-flight requests finish,
+/// and objects of the local storage under `/files` from the `files` directory
+pub async fn start_api(
+    provider: Arc<Provider>,
+    files: Option<&Path>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
     let state = Arc::new(AppState(provider));
-    let router = routers()
+    let router = routers(files)
         .with_state(state)
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
     let port = std::env::var("PORT").unwrap_or("9001".to_string());
     tracing::info!("Listen port: {}", port);
     let listener = tokio::net::TcpListener::bind(&format!("0.0.0.0:{}", port)).await?;
-    axum::serve(listener, router).await?;
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown)
+        .await?;
     Ok(())
 }
@@ -1,18 +1,23 @@
 use super::AppState;
 use crate::{
     error::Error,
-    usecase::{task_service::Args, Provider, UserApiClientProvider},
+    usecase::{
+        pronunciation_service::PronunciationInput, retry_policy::RetryPolicy,
+        schedule_service::ScheduleInput, task_service::Args, Provider, UserApiClientProvider,
+    },
 };
 use axum::{
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
     Json, Router,
 };
-use repos::entity::ScriptId;
+use repos::entity::{PodcastId, PronunciationId, ScheduleId, ScriptId, TaskId, TaskStatus};
 use serde_json::{json, Value};
 use std::{collections::BTreeMap, sync::Arc};
+use tokio_util::sync::CancellationToken;
+use tower_http::services::ServeDir;
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -63,21 +68,244 @@ async fn eval_template(
 
     let evaluated = provider
         .script_service()
-        .run_template(&template, arguments)
+        .run_template(&template, arguments, &CancellationToken::new())
         .await?;
     Ok(Json(evaluated))
 }
 
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateTaskRequest {
+    #[serde(flatten)]
+    args: Args,
+    retry_policy: Option<RetryPolicy>,
+    timeout_sec: Option<i32>,
+}
+
 #[instrument(skip(state))]
 async fn create_task(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(args): Json<Args>,
+    Json(CreateTaskRequest {
+        args,
+        retry_policy,
+        timeout_sec,
+    }): Json<CreateTaskRequest>,
 ) -> Result<impl IntoResponse, Error> {
     let provider = with_user_api_client(&state.0, get_authorization(&headers));
 
-    provider.task_service().create_task(args).await?;
-    Ok(StatusCode::CREATED)
+    let task_id = provider
+        .task_service()
+        .create_task(args, retry_policy, timeout_sec)
+        .await?;
+    Ok((StatusCode::CREATED, Json(json!({ "id": task_id }))))
+}
+
+const MAX_TASKS_LIMIT: i64 = 100;
+
+#[derive(Debug, serde::Deserialize)]
+struct ListTasksQuery {
+    status: Option<TaskStatus>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+    #[serde(default)]
+    offset: i64,
+}
+
+fn default_limit() -> i64 {
+    20
+}
+
+#[instrument(skip(state))]
+async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(ListTasksQuery {
+        status,
+        limit,
+        offset,
+    }): Query<ListTasksQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let tasks = provider
+        .task_service()
+        .list_tasks(status, limit.clamp(1, MAX_TASKS_LIMIT), offset.max(0))
+        .await?;
+    Ok(Json(tasks))
+}
+
+#[instrument(skip(state))]
+async fn get_task(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(task_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let task = provider.task_service().get_task(&TaskId(task_id)).await?;
+    Ok(Json(task))
+}
+
+#[instrument(skip(state))]
+async fn cancel_task(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(task_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let task = provider
+        .task_service()
+        .cancel_task(&TaskId(task_id))
+        .await?;
+    Ok(Json(task))
+}
+
+#[instrument(skip(state))]
+async fn retry_task(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(task_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let task = provider.task_service().retry_task(&TaskId(task_id)).await?;
+    Ok(Json(task))
+}
+
+#[instrument(skip(state))]
+async fn create_schedule(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(input): Json<ScheduleInput>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let schedule_id = provider.schedule_service().create_schedule(input).await?;
+    Ok((StatusCode::CREATED, Json(json!({ "id": schedule_id }))))
+}
+
+#[instrument(skip(state))]
+async fn list_schedules(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let schedules = provider.schedule_service().list_schedules().await?;
+    Ok(Json(schedules))
+}
+
+#[instrument(skip(state))]
+async fn get_schedule(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let schedule = provider
+        .schedule_service()
+        .get_schedule(&ScheduleId(schedule_id))
+        .await?;
+    Ok(Json(schedule))
+}
+
+#[instrument(skip(state))]
+async fn update_schedule(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(schedule_id): Path<Uuid>,
+    Json(input): Json<ScheduleInput>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let schedule = provider
+        .schedule_service()
+        .update_schedule(&ScheduleId(schedule_id), input)
+        .await?;
+    Ok(Json(schedule))
+}
+
+#[instrument(skip(state))]
+async fn delete_schedule(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    provider
+        .schedule_service()
+        .delete_schedule(&ScheduleId(schedule_id))
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListPronunciationsQuery {
+    podcast_id: Option<Uuid>,
+}
+
+#[instrument(skip(state))]
+async fn list_pronunciations(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(ListPronunciationsQuery { podcast_id }): Query<ListPronunciationsQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let pronunciations = provider
+        .pronunciation_service()
+        .list_pronunciations(podcast_id)
+        .await?;
+    Ok(Json(pronunciations))
+}
+
+#[instrument(skip(state))]
+async fn upsert_pronunciation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(input): Json<PronunciationInput>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    let pronunciation = provider
+        .pronunciation_service()
+        .upsert_pronunciation(input)
+        .await?;
+    Ok(Json(pronunciation))
+}
+
+#[instrument(skip(state))]
+async fn delete_pronunciation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(pronunciation_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let provider = with_user_api_client(&state.0, get_authorization(&headers));
+
+    provider
+        .pronunciation_service()
+        .delete_pronunciation(&PronunciationId(pronunciation_id))
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// rss of a podcast, deliberately unauthenticated since podcast apps fetch feeds without credentials.
+/// it only has what is published in the storage anyway
+#[instrument(skip(state))]
+async fn get_feed(
+    State(state): State<Arc<AppState>>,
+    Path(podcast_id): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let feed = state.0.feed_service().feed(&PodcastId(podcast_id)).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        feed,
+    ))
 }
 
 async fn version() -> Result<impl IntoResponse, Error> {
@@ -87,10 +315,35 @@ async fn version() -> Result<impl IntoResponse, Error> {
     })))
 }
 
-pub(crate) fn routers() -> Router<Arc<AppState>> {
-    Router::new()
+/// objects of the local storage are served from `files` with range and conditional requests
+pub(crate) fn routers(files: Option<&std::path::Path>) -> Router<Arc<AppState>> {
+    let router = Router::new()
         .route("/version", get(version))
         .route("/scripts/:script_id", post(update_script))
         .route("/createTask", post(create_task))
+        .route("/tasks", get(list_tasks))
+        .route("/tasks/:task_id", get(get_task))
+        .route("/tasks/:task_id/cancel", post(cancel_task))
+        .route("/tasks/:task_id/retry", post(retry_task))
+        .route("/schedules", get(list_schedules).post(create_schedule))
+        .route(
+            "/schedules/:schedule_id",
+            get(get_schedule)
+                .put(update_schedule)
+                .delete(delete_schedule),
+        )
+        .route(
+            "/pronunciations",
+            get(list_pronunciations).post(upsert_pronunciation),
+        )
+        .route(
+            "/pronunciations/:pronunciation_id",
+            delete(delete_pronunciation),
+        )
         .route("/evalTemplate", post(eval_template))
+        .route("/podcasts/:podcast_id/feed.xml", get(get_feed));
+    if let Some(files) = files {
+        return router.nest_service("/files", ServeDir::new(files));
+    }
+    router
 }
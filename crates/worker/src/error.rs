@@ -10,6 +10,8 @@ pub(crate) enum Error {
     InvalidInput(anyhow::Error),
     #[error("UnAuthorized")]
     UnAuthorized,
+    #[error("Timeout: exceeded {0:?}")]
+    Timeout(std::time::Duration),
     #[error("Other: {0}")]
     Other(anyhow::Error),
 }
@@ -19,3 +21,25 @@ impl From<repos::error::Error> for Error {
         Error::Repo(e)
     }
 }
+
+impl Error {
+    /// adds context to the inner error, keeping the variant
+    pub(crate) fn context(self, context: String) -> Self {
+        match self {
+            Error::Script(e) => Error::Script(e.context(context)),
+            Error::InvalidInput(e) => Error::InvalidInput(e.context(context)),
+            Error::Other(e) => Error::Other(e.context(context)),
+            e => e,
+        }
+    }
+
+    /// errors which never succeed on retry
+    pub(crate) fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidInput(_)
+                | Error::UnAuthorized
+                | Error::Repo(repos::error::Error::NotFound(..))
+        )
+    }
+}
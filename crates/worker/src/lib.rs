@@ -1,5 +1,5 @@
 pub mod api;
 pub mod error;
-pub mod r2_storage;
+pub mod storage;
 pub mod usecase;
 pub mod worker;
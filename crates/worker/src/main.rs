@@ -1,16 +1,24 @@
+use audio_generator::tts::{TtsConfig, TtsRegistry};
 use opentelemetry::trace::TracerProvider as _;
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::runtime::Tokio;
 use opentelemetry_sdk::trace::TracerProvider;
 use opentelemetry_sdk::Resource;
-use std::{str::FromStr, sync::Arc};
+use std::{future::Future, str::FromStr, sync::Arc};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::EnvFilter;
-use worker::{api::start_api, usecase::Provider, worker::start_worker};
+use worker::{
+    api::start_api,
+    storage::StorageConfig,
+    usecase::Provider,
+    worker::{start_worker, WorkerConfig},
+};
 
-fn init_tracing(otlp_collector_endpoint: String) -> anyhow::Result<()> {
+fn init_tracing(otlp_collector_endpoint: String) -> anyhow::Result<TracerProvider> {
     let crate_name = env!("CARGO_CRATE_NAME");
 
     let subscriber = tracing_subscriber::registry();
@@ -35,15 +43,59 @@ fn init_tracing(otlp_collector_endpoint: String) -> anyhow::Result<()> {
     let fmt_layer = tracing_subscriber::fmt::layer().pretty();
     let subscriber = subscriber.with(fmt_layer);
     tracing::subscriber::set_global_default(subscriber)?;
-    Ok(())
+    Ok(tracer_provider)
+}
+
+/// installs the handlers up front, so that a failure stops the worker before it starts
+fn shutdown_signal() -> anyhow::Result<impl Future<Output = ()>> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = interrupt.recv() => {},
+            _ = terminate.recv() => {},
+        }
+        tracing::info!("Shutting down");
+    })
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let otlp_collector_endpoint = std::env::var("OTLP_COLLECTOR_ENDPOINT")?;
-    init_tracing(otlp_collector_endpoint)?;
+    let tracer_provider = init_tracing(otlp_collector_endpoint)?;
+    // NOTE: fail fast rather than on the first episode
+    let ffmpeg = audio_generator::ffmpeg::check_ffmpeg().await?;
+    tracing::info!("{}", ffmpeg);
+    // NOTE: replicas starting at once race on migrations, so `just migrate` is the default
+    let migrate_on_start: bool = std::env::var("MIGRATE_ON_START")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .unwrap_or("false".to_string())
+        .parse()?;
+    if migrate_on_start {
+        repos::postgres::migrate().await?;
+    }
 
-    let provider = Arc::new(Provider::default());
-    start_worker(provider.clone());
-    start_api(provider).await
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    let shutdown = shutdown_signal()?;
+    tokio::spawn(async move {
+        shutdown.await;
+        shutdown_tx.send_replace(true);
+    });
+
+    let storage_config = StorageConfig::from_env()?;
+    let tts = TtsRegistry::new(TtsConfig::load()?);
+    let provider = Arc::new(Provider::new(storage_config.build()?, Arc::new(tts)));
+    let worker = start_worker(
+        provider.clone(),
+        WorkerConfig::from_env()?,
+        shutdown_rx.clone(),
+    );
+    start_api(provider, storage_config.served_dir(), async move {
+        let _ = shutdown_rx.wait_for(|stop| *stop).await;
+    })
+    .await?;
+    worker.drain().await?;
+    tracer_provider.shutdown()?;
+    Ok(())
 }
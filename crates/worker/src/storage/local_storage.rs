@@ -0,0 +1,172 @@
+use super::{
+    join_url,
+    multipart::{digest, UploadedFile},
+    Storage,
+};
+use anyhow::Context;
+use axum::async_trait;
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::fs;
+use uuid::Uuid;
+
+/// suffix of files being written
+const PARTIAL: &str = ".partial";
+
+/// keeps objects as files under a directory
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: Option<String>,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf, public_url: Option<String>) -> Self {
+        Self { root, public_url }
+    }
+
+    /// the file of an object, rejecting paths out of the root
+    fn file(&self, path: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(path);
+        if path.is_empty()
+            || path.ends_with(PARTIAL)
+            || relative
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            anyhow::bail!("Invalid storage path: {}", path);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// relative paths of files under `dir`
+    fn walk(dir: &Path, prefix: &str, paths: &mut Vec<String>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = format!("{}{}", prefix, name);
+            if entry.file_type()?.is_dir() {
+                Self::walk(&entry.path(), &format!("{}/", path), paths)?;
+            } else if !name.ends_with(PARTIAL) {
+                paths.push(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn upload(&self, path: &str, data: &[u8], _content_type: &str) -> anyhow::Result<()> {
+        let file = self.file(path)?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        // NOTE: readers never see a half written file
+        let partial = file.with_file_name(format!("{}{}", Uuid::new_v4(), PARTIAL));
+        fs::write(&partial, data)
+            .await
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        fs::rename(&partial, &file).await?;
+        Ok(())
+    }
+
+    async fn upload_file(
+        &self,
+        path: &str,
+        file: &Path,
+        _content_type: &str,
+    ) -> anyhow::Result<UploadedFile> {
+        let target = self.file(path)?;
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let partial = target.with_file_name(format!("{}{}", Uuid::new_v4(), PARTIAL));
+        fs::copy(file, &partial)
+            .await
+            .with_context(|| format!("Failed to copy {}", file.display()))?;
+        let uploaded = digest(&partial).await?;
+        fs::rename(&partial, &target).await?;
+        Ok(uploaded)
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let file = self.file(path)?;
+        let data = fs::read(&file)
+            .await
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        Ok(data)
+    }
+
+    async fn exists(&self, path: &str) -> anyhow::Result<bool> {
+        Ok(fs::try_exists(self.file(path)?).await?)
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        match fs::remove_file(self.file(path)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let root = self.root.clone();
+        let mut paths = tokio::task::spawn_blocking(move || {
+            let mut paths = vec![];
+            match Self::walk(&root, "", &mut paths) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(paths),
+            }
+        })
+        .await??;
+        paths.retain(|path| path.starts_with(prefix));
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// files are served without signatures
+    async fn presigned_url(&self, _path: &str, _expires_in: Duration) -> anyhow::Result<String> {
+        anyhow::bail!("Local storage does not presign urls")
+    }
+
+    fn public_url(&self, path: &str) -> Option<String> {
+        let base = self.public_url.as_deref()?;
+        Some(join_url(base, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_storage() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("storage-{}", Uuid::new_v4()));
+        let storage = LocalStorage::new(root.clone(), Some("http://localhost/files".to_string()));
+        assert_eq!(storage.list("").await?, Vec::<String>::new());
+
+        storage.upload("episodes/a.mp3", b"a", "audio/mpeg").await?;
+        storage.upload("episodes/a.srt", b"b", "text/plain").await?;
+        storage.upload("feed.xml", b"c", "application/xml").await?;
+        assert_eq!(storage.get("episodes/a.mp3").await?, b"a");
+        assert_eq!(
+            storage.list("episodes/").await?,
+            vec!["episodes/a.mp3", "episodes/a.srt"]
+        );
+
+        storage.delete("episodes/a.mp3").await?;
+        storage.delete("episodes/a.mp3").await?;
+        assert!(!storage.exists("episodes/a.mp3").await?);
+        assert!(storage.get("../a.mp3").await.is_err());
+        assert!(storage.exists("/etc/passwd").await.is_err());
+        assert_eq!(
+            storage.public_url("feed.xml").as_deref(),
+            Some("http://localhost/files/feed.xml")
+        );
+        std::fs::remove_dir_all(root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use super::{
+    join_url,
+    multipart::{
+        upload_in_parts, MultipartConfig, MultipartUpload, UploadNotFound, UploadedFile,
+        UploadedPart,
+    },
+    Storage,
+};
+use axum::async_trait;
+use std::{collections::BTreeMap, path::Path, sync::Mutex, time::Duration};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+struct Object {
+    data: Vec<u8>,
+    content_type: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Upload {
+    content_type: String,
+    parts: BTreeMap<u32, Vec<u8>>,
+}
+
+/// keeps objects in memory, for tests and dry runs
+#[derive(Debug)]
+pub struct MemoryStorage {
+    objects: Mutex<BTreeMap<String, Object>>,
+    /// multipart uploads by id
+    uploads: Mutex<BTreeMap<String, Upload>>,
+    public_url: Option<String>,
+    multipart: MultipartConfig,
+}
+
+impl MemoryStorage {
+    pub fn new(public_url: Option<String>) -> Self {
+        Self {
+            objects: Mutex::default(),
+            uploads: Mutex::default(),
+            public_url,
+            multipart: MultipartConfig::from_env(),
+        }
+    }
+
+    pub fn content_type(&self, path: &str) -> Option<String> {
+        let objects = self.objects.lock().unwrap();
+        objects.get(path).map(|object| object.content_type.clone())
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn upload(&self, path: &str, data: &[u8], content_type: &str) -> anyhow::Result<()> {
+        let object = Object {
+            data: data.to_vec(),
+            content_type: content_type.to_string(),
+        };
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), object);
+        Ok(())
+    }
+
+    async fn upload_file(
+        &self,
+        path: &str,
+        file: &Path,
+        content_type: &str,
+    ) -> anyhow::Result<UploadedFile> {
+        upload_in_parts(self, path, file, content_type, &self.multipart).await
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let objects = self.objects.lock().unwrap();
+        let object = objects
+            .get(path)
+            .ok_or_else(|| anyhow::anyhow!("{} not found", path))?;
+        Ok(object.data.clone())
+    }
+
+    async fn exists(&self, path: &str) -> anyhow::Result<bool> {
+        Ok(self.objects.lock().unwrap().contains_key(path))
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        self.objects.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects
+            .keys()
+            .filter(|path| path.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn presigned_url(&self, _path: &str, _expires_in: Duration) -> anyhow::Result<String> {
+        anyhow::bail!("Memory storage does not presign urls")
+    }
+
+    fn public_url(&self, path: &str) -> Option<String> {
+        let base = self.public_url.as_deref()?;
+        Some(join_url(base, path))
+    }
+}
+
+#[async_trait]
+impl MultipartUpload for MemoryStorage {
+    async fn create_multipart(&self, _path: &str, content_type: &str) -> anyhow::Result<String> {
+        let upload_id = Uuid::new_v4().to_string();
+        let upload = Upload {
+            content_type: content_type.to_string(),
+            ..Default::default()
+        };
+        self.uploads
+            .lock()
+            .unwrap()
+            .insert(upload_id.clone(), upload);
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        _path: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+        _content_type: &str,
+    ) -> anyhow::Result<String> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads
+            .get_mut(upload_id)
+            .ok_or_else(|| UploadNotFound(upload_id.to_string()))?;
+        let etag = format!("\"{:x}\"", md5::compute(&data));
+        upload.parts.insert(number, data);
+        Ok(etag)
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        let upload = self
+            .uploads
+            .lock()
+            .unwrap()
+            .remove(upload_id)
+            .ok_or_else(|| UploadNotFound(upload_id.to_string()))?;
+        let mut data = vec![];
+        for part in parts {
+            let part = upload
+                .parts
+                .get(&part.number)
+                .ok_or_else(|| anyhow::anyhow!("Part {} not found", part.number))?;
+            data.extend_from_slice(part);
+        }
+        let object = Object {
+            data,
+            content_type: upload.content_type,
+        };
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), object);
+        Ok(())
+    }
+
+    async fn abort_multipart(&self, _path: &str, upload_id: &str) -> anyhow::Result<()> {
+        self.uploads.lock().unwrap().remove(upload_id);
+        Ok(())
+    }
+
+    async fn object_size(&self, path: &str) -> anyhow::Result<Option<u64>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects.get(path).map(|object| object.data.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_storage() -> anyhow::Result<()> {
+        let storage = MemoryStorage::new(Some("http://localhost/memory".to_string()));
+        storage.upload("episodes/a.mp3", b"a", "audio/mpeg").await?;
+        storage.upload("episodes/a.srt", b"b", "text/plain").await?;
+        storage
+            .upload("podcasts/a.xml", b"c", "application/rss+xml")
+            .await?;
+
+        assert_eq!(storage.get("episodes/a.mp3").await?, b"a");
+        assert!(storage.get("episodes/b.mp3").await.is_err());
+        assert_eq!(
+            storage.content_type("episodes/a.srt").as_deref(),
+            Some("text/plain")
+        );
+        assert_eq!(
+            storage.list("episodes/").await?,
+            vec!["episodes/a.mp3", "episodes/a.srt"]
+        );
+
+        storage.delete("episodes/a.mp3").await?;
+        storage.delete("episodes/a.mp3").await?;
+        assert!(!storage.exists("episodes/a.mp3").await?);
+        assert_eq!(
+            storage.public_url("podcasts/a.xml").as_deref(),
+            Some("http://localhost/memory/podcasts/a.xml")
+        );
+        Ok(())
+    }
+}
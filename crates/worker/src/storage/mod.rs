@@ -0,0 +1,243 @@
+pub mod local_storage;
+pub mod memory_storage;
+pub mod multipart;
+pub mod s3_storage;
+
+use anyhow::Context;
+use axum::async_trait;
+use local_storage::LocalStorage;
+use memory_storage::MemoryStorage;
+use multipart::UploadedFile;
+use s3_storage::S3Storage;
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    async fn upload(&self, path: &str, data: &[u8], content_type: &str) -> anyhow::Result<()>;
+
+    /// streams a file without reading it into memory at once,
+    /// resuming an interrupted upload of the same file if the storage supports it
+    async fn upload_file(
+        &self,
+        path: &str,
+        file: &Path,
+        content_type: &str,
+    ) -> anyhow::Result<UploadedFile>;
+
+    async fn get(&self, path: &str) -> anyhow::Result<Vec<u8>>;
+
+    async fn exists(&self, path: &str) -> anyhow::Result<bool>;
+
+    /// succeeds even if the object does not exist
+    async fn delete(&self, path: &str) -> anyhow::Result<()>;
+
+    /// paths of objects starting with `prefix`
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>>;
+
+    /// a url which grants reading the object until it expires
+    async fn presigned_url(&self, path: &str, expires_in: Duration) -> anyhow::Result<String>;
+
+    /// the absolute url clients fetch the object from, `None` without `STORAGE_PUBLIC_URL`
+    fn public_url(&self, path: &str) -> Option<String>;
+}
+
+pub(crate) trait ProvideStorage: Debug + Send + Sync {
+    fn storage(&self) -> Arc<dyn Storage>;
+}
+
+/// shares a storage built at startup
+#[derive(Debug, Clone)]
+pub(crate) struct StorageProvider {
+    storage: Arc<dyn Storage>,
+}
+
+impl StorageProvider {
+    pub(crate) fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+}
+
+impl ProvideStorage for StorageProvider {
+    fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+}
+
+/// `path` under `base`
+pub(crate) fn join_url(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageBackend {
+    /// Cloudflare R2
+    R2 { account_id: String, bucket: String },
+    /// S3 compatible storages e.g. MinIO
+    S3 {
+        endpoint: String,
+        region: String,
+        bucket: String,
+        /// `endpoint/bucket/path` instead of `bucket.endpoint/path`, required by MinIO
+        path_style: bool,
+    },
+    /// a directory served by the worker under `/files`
+    Local { root: PathBuf },
+    /// lost on exit
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// `STORAGE_PUBLIC_URL`, where objects are served
+    pub public_url: Option<String>,
+}
+
+impl StorageConfig {
+    const DEFAULT_BUCKET: &'static str = "botcast";
+
+    /// reads `STORAGE_BACKEND` (`r2` by default, `s3`, `local` or `memory`) and its options
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        // NOTE: compose passes unset variables as empty strings
+        let var = |key: &str| var(key).filter(|value| !value.is_empty());
+        let required = |key: &str, backend: &str| {
+            var(key).with_context(|| format!("{} is required for the {} storage", key, backend))
+        };
+        let bucket = var("STORAGE_BUCKET").unwrap_or(Self::DEFAULT_BUCKET.to_string());
+        let backend = match var("STORAGE_BACKEND").as_deref().unwrap_or("r2") {
+            "r2" => StorageBackend::R2 {
+                account_id: required("CLOUDFLARE_ACCOUNT_ID", "r2")?,
+                bucket,
+            },
+            "s3" => StorageBackend::S3 {
+                endpoint: required("STORAGE_ENDPOINT", "s3")?,
+                region: var("STORAGE_REGION").unwrap_or("us-east-1".to_string()),
+                bucket,
+                path_style: var("STORAGE_PATH_STYLE")
+                    .map(|v| v.parse())
+                    .transpose()
+                    .context("STORAGE_PATH_STYLE must be true or false")?
+                    .unwrap_or(true),
+            },
+            "local" => StorageBackend::Local {
+                root: var("STORAGE_LOCAL_ROOT")
+                    .unwrap_or("storage".to_string())
+                    .into(),
+            },
+            "memory" => StorageBackend::Memory,
+            backend => anyhow::bail!("Unknown STORAGE_BACKEND: {}", backend),
+        };
+        // NOTE: urls are persisted, so the local storage does not guess where it is served
+        Ok(Self {
+            backend,
+            public_url: var("STORAGE_PUBLIC_URL"),
+        })
+    }
+
+    /// the directory the worker serves under `/files`
+    pub fn served_dir(&self) -> Option<&Path> {
+        match &self.backend {
+            StorageBackend::Local { root } => Some(root),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self) -> anyhow::Result<Arc<dyn Storage>> {
+        if self.public_url.is_none() {
+            tracing::warn!("STORAGE_PUBLIC_URL is not set, episodes and feeds are not published");
+        }
+        let public_url = self.public_url.clone();
+        let storage: Arc<dyn Storage> = match &self.backend {
+            StorageBackend::R2 { account_id, bucket } => {
+                Arc::new(S3Storage::r2(account_id, bucket, public_url)?)
+            }
+            StorageBackend::S3 {
+                endpoint,
+                region,
+                bucket,
+                path_style,
+            } => Arc::new(S3Storage::new(
+                endpoint,
+                region,
+                bucket,
+                *path_style,
+                public_url,
+            )?),
+            StorageBackend::Local { root } => Arc::new(LocalStorage::new(root.clone(), public_url)),
+            StorageBackend::Memory => Arc::new(MemoryStorage::new(public_url)),
+        };
+        Ok(storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn config(vars: &[(&str, &str)]) -> anyhow::Result<StorageConfig> {
+        let vars: BTreeMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        StorageConfig::from_vars(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_storage_config() -> anyhow::Result<()> {
+        assert!(config(&[]).is_err());
+        assert!(config(&[("CLOUDFLARE_ACCOUNT_ID", "")]).is_err());
+        assert_eq!(
+            config(&[("CLOUDFLARE_ACCOUNT_ID", "a")])?.backend,
+            StorageBackend::R2 {
+                account_id: "a".to_string(),
+                bucket: "botcast".to_string(),
+            }
+        );
+        assert_eq!(
+            config(&[
+                ("STORAGE_BACKEND", "s3"),
+                ("STORAGE_ENDPOINT", "http://minio:9000"),
+                ("STORAGE_BUCKET", "podcasts"),
+                ("STORAGE_PATH_STYLE", ""),
+            ])?
+            .backend,
+            StorageBackend::S3 {
+                endpoint: "http://minio:9000".to_string(),
+                region: "us-east-1".to_string(),
+                bucket: "podcasts".to_string(),
+                path_style: true,
+            }
+        );
+        let local = config(&[("STORAGE_BACKEND", "local"), ("PORT", "8080")])?;
+        assert_eq!(local.served_dir(), Some(Path::new("storage")));
+        assert_eq!(local.public_url, None);
+        assert!(config(&[("STORAGE_BACKEND", "gcs")]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_url() {
+        assert_eq!(
+            join_url("https://cdn.example.com/", "/episodes/a.mp3"),
+            "https://cdn.example.com/episodes/a.mp3"
+        );
+        assert_eq!(
+            join_url("https://cdn.example.com/botcast", "episodes/a.mp3"),
+            "https://cdn.example.com/botcast/episodes/a.mp3"
+        );
+    }
+}
@@ -0,0 +1,521 @@
+use anyhow::Context;
+use axum::async_trait;
+use sha2::{Digest, Sha256};
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+/// S3 requires parts of at least 5 MiB except the last one
+const PART_SIZE: usize = 8 * 1024 * 1024;
+const PART_RETRIES: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// how files are uploaded in parts
+#[derive(Debug, Clone)]
+pub struct MultipartConfig {
+    pub part_size: usize,
+    /// `UPLOAD_STATE_DIR`, where progress of uploads is kept to resume them by later tasks
+    pub state_dir: PathBuf,
+}
+
+impl MultipartConfig {
+    pub fn from_env() -> Self {
+        let state_dir = std::env::var("UPLOAD_STATE_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or("temp/uploads".to_string());
+        Self {
+            part_size: PART_SIZE,
+            state_dir: PathBuf::from(state_dir),
+        }
+    }
+}
+
+/// a file in storage. `sha256` of the whole content is recorded for clients and not verified
+/// by the storage, uploads are verified by md5 etags of their parts and the stored size
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadedFile {
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UploadedPart {
+    pub number: u32,
+    pub etag: String,
+    /// hex md5 of the part, to skip unchanged parts on resume
+    pub md5: String,
+}
+
+/// the storage does not know the upload, e.g. it was aborted or expired by a lifecycle rule
+#[derive(Debug, thiserror::Error)]
+#[error("Upload {0} not found")]
+pub struct UploadNotFound(pub String);
+
+/// progress of a multipart upload, kept by its storage path to resume it,
+/// since work dirs of failed tasks are removed
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct UploadState {
+    path: String,
+    upload_id: String,
+    part_size: usize,
+    parts: Vec<UploadedPart>,
+}
+
+impl UploadState {
+    fn file(dir: &Path, path: &str) -> PathBuf {
+        dir.join(format!("{}.json", hex::encode(Sha256::digest(path))))
+    }
+
+    async fn load(dir: &Path, path: &str) -> Option<Self> {
+        let state = fs::read(Self::file(dir, path)).await.ok()?;
+        let state: Self = serde_json::from_slice(&state).ok()?;
+        (state.path == path).then_some(state)
+    }
+
+    async fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(dir).await?;
+        fs::write(Self::file(dir, &self.path), serde_json::to_vec(self)?).await?;
+        Ok(())
+    }
+
+    async fn remove(dir: &Path, path: &str) -> anyhow::Result<()> {
+        match fs::remove_file(Self::file(dir, path)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// primitives of storages which accept a file in parts
+#[async_trait]
+pub trait MultipartUpload: Send + Sync {
+    /// returns the upload id
+    async fn create_multipart(&self, path: &str, content_type: &str) -> anyhow::Result<String>;
+
+    /// returns the etag of the part, fails with `UploadNotFound` for an unknown upload
+    async fn upload_part(
+        &self,
+        path: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+        content_type: &str,
+    ) -> anyhow::Result<String>;
+
+    /// fails with `UploadNotFound` for an unknown upload
+    async fn complete_multipart(
+        &self,
+        path: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()>;
+
+    /// discards uploaded parts, succeeds even if the upload does not exist
+    async fn abort_multipart(&self, path: &str, upload_id: &str) -> anyhow::Result<()>;
+
+    /// size of the stored object, `None` if it does not exist
+    async fn object_size(&self, path: &str) -> anyhow::Result<Option<u64>>;
+}
+
+/// reads up to `buf.len()` bytes, less only at the end of the file
+async fn read_part(file: &mut fs::File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// size and sha256 of a file, read in parts
+pub(crate) async fn digest(file: &Path) -> anyhow::Result<UploadedFile> {
+    let mut reader = fs::File::open(file).await?;
+    let mut buf = vec![0; 1024 * 1024];
+    let mut sha256 = Sha256::new();
+    let mut size = 0;
+    loop {
+        let len = read_part(&mut reader, &mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        sha256.update(&buf[..len]);
+        size += len as u64;
+    }
+    Ok(UploadedFile {
+        size,
+        sha256: hex::encode(sha256.finalize()),
+    })
+}
+
+/// the part of `len` bytes at `offset` of a file
+async fn read_part_at(file: &Path, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut reader = fs::File::open(file).await?;
+    reader.seek(SeekFrom::Start(offset)).await?;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// uploads a part, retrying on errors and on an etag which is not the md5 of the part.
+/// a retry reads the part from `file` again rather than keeping a copy of it
+async fn upload_part(
+    storage: &dyn MultipartUpload,
+    state: &UploadState,
+    file: &Path,
+    number: u32,
+    data: Vec<u8>,
+    md5: &str,
+    content_type: &str,
+) -> anyhow::Result<String> {
+    let offset = (number as u64 - 1) * state.part_size as u64;
+    let len = data.len();
+    let mut first = Some(data);
+    let mut attempt = 0;
+    loop {
+        let data = match first.take() {
+            Some(data) => data,
+            None => read_part_at(file, offset, len).await?,
+        };
+        let res = storage
+            .upload_part(&state.path, &state.upload_id, number, data, content_type)
+            .await
+            .and_then(|etag| {
+                // NOTE: etags of encrypted objects are not md5 and can not be verified
+                let plain = etag.trim_matches('"');
+                if plain.len() == 32 && plain.chars().all(|c| c.is_ascii_hexdigit()) && plain != md5
+                {
+                    anyhow::bail!(
+                        "Part {} is corrupted: etag {} but md5 {}",
+                        number,
+                        plain,
+                        md5
+                    );
+                }
+                Ok(etag)
+            });
+        match res {
+            Ok(etag) => return Ok(etag),
+            Err(e) if attempt < PART_RETRIES && !e.is::<UploadNotFound>() => {
+                attempt += 1;
+                tracing::warn!("Failed to upload part {}, retrying: {:?}", number, e);
+                tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+            }
+            Err(e) => return Err(e.context(format!("Failed to upload part {}", number))),
+        }
+    }
+}
+
+/// discards an upload, failures are only logged
+async fn abort(storage: &dyn MultipartUpload, state: &UploadState) {
+    if let Err(e) = storage.abort_multipart(&state.path, &state.upload_id).await {
+        tracing::warn!("Failed to abort upload {}: {:?}", state.upload_id, e);
+    }
+}
+
+/// streams a file in parts, retrying each part.
+/// an interrupted upload is resumed by the next call with the same path, skipping unchanged parts
+pub(crate) async fn upload_in_parts(
+    storage: &dyn MultipartUpload,
+    path: &str,
+    file: &Path,
+    content_type: &str,
+    config: &MultipartConfig,
+) -> anyhow::Result<UploadedFile> {
+    match UploadState::load(&config.state_dir, path).await {
+        Some(state) if state.part_size == config.part_size => {
+            tracing::info!("Resuming upload {} of {}", state.upload_id, path);
+            let upload_id = state.upload_id.clone();
+            match upload(storage, state, file, content_type, config).await {
+                // NOTE: the upload is gone with its parts, so the file is uploaded again
+                Err(e) if e.is::<UploadNotFound>() => {
+                    tracing::warn!("Upload {} of {} not found, restarting", upload_id, path);
+                    UploadState::remove(&config.state_dir, path).await?;
+                }
+                res => return res,
+            }
+        }
+        Some(state) => abort(storage, &state).await,
+        None => {}
+    }
+    let state = UploadState {
+        path: path.to_string(),
+        upload_id: storage.create_multipart(path, content_type).await?,
+        part_size: config.part_size,
+        parts: vec![],
+    };
+    upload(storage, state, file, content_type, config).await
+}
+
+/// uploads parts of a file not in `state` yet and completes the upload
+async fn upload(
+    storage: &dyn MultipartUpload,
+    mut state: UploadState,
+    file: &Path,
+    content_type: &str,
+    config: &MultipartConfig,
+) -> anyhow::Result<UploadedFile> {
+    let path = state.path.clone();
+    let part_size = state.part_size;
+    state.save(&config.state_dir).await?;
+
+    let mut reader = fs::File::open(file)
+        .await
+        .with_context(|| format!("Failed to open {}", file.display()))?;
+    let mut sha256 = Sha256::new();
+    let mut size = 0;
+    let mut parts = vec![];
+    for number in 1.. {
+        let mut data = vec![0; part_size];
+        let len = read_part(&mut reader, &mut data).await?;
+        // NOTE: an empty file is uploaded as an empty part
+        if len == 0 && number > 1 {
+            break;
+        }
+        data.truncate(len);
+        sha256.update(&data);
+        size += len as u64;
+
+        let md5 = format!("{:x}", md5::compute(&data));
+        let uploaded = state
+            .parts
+            .iter()
+            .find(|part| part.number == number && part.md5 == md5)
+            .cloned();
+        let part = match uploaded {
+            Some(part) => part,
+            None => {
+                let etag =
+                    upload_part(storage, &state, file, number, data, &md5, content_type).await?;
+                let part = UploadedPart { number, etag, md5 };
+                state.parts.retain(|p| p.number != number);
+                state.parts.push(part.clone());
+                state.save(&config.state_dir).await?;
+                part
+            }
+        };
+        parts.push(part);
+        if len < part_size {
+            break;
+        }
+    }
+
+    // NOTE: parts failed beyond retries are kept to resume,
+    // but an upload which can not be completed is discarded not to leave its parts
+    let completed = storage
+        .complete_multipart(&path, &state.upload_id, &parts)
+        .await
+        .with_context(|| format!("Failed to complete upload of {}", path));
+    if completed.is_err() {
+        abort(storage, &state).await;
+    }
+    UploadState::remove(&config.state_dir, &path).await?;
+    completed?;
+    let stored = storage.object_size(&path).await?;
+    if stored != Some(size) {
+        anyhow::bail!(
+            "Uploaded {} has {:?} bytes but {} bytes are expected",
+            path,
+            stored,
+            size
+        );
+    }
+    Ok(UploadedFile {
+        size,
+        sha256: hex::encode(sha256.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{memory_storage::MemoryStorage, Storage};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    /// fails uploads of the given part numbers once each
+    #[derive(Debug)]
+    struct Flaky {
+        inner: MemoryStorage,
+        failures: Mutex<Vec<u32>>,
+        uploads: Mutex<Vec<u32>>,
+        aborts: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl MultipartUpload for Flaky {
+        async fn create_multipart(&self, path: &str, content_type: &str) -> anyhow::Result<String> {
+            self.inner.create_multipart(path, content_type).await
+        }
+
+        async fn upload_part(
+            &self,
+            path: &str,
+            upload_id: &str,
+            number: u32,
+            data: Vec<u8>,
+            content_type: &str,
+        ) -> anyhow::Result<String> {
+            self.uploads.lock().unwrap().push(number);
+            {
+                let mut failures = self.failures.lock().unwrap();
+                if let Some(i) = failures.iter().position(|&n| n == number) {
+                    failures.remove(i);
+                    anyhow::bail!("connection reset");
+                }
+            }
+            self.inner
+                .upload_part(path, upload_id, number, data, content_type)
+                .await
+        }
+
+        async fn complete_multipart(
+            &self,
+            path: &str,
+            upload_id: &str,
+            parts: &[UploadedPart],
+        ) -> anyhow::Result<()> {
+            self.inner.complete_multipart(path, upload_id, parts).await
+        }
+
+        async fn abort_multipart(&self, path: &str, upload_id: &str) -> anyhow::Result<()> {
+            self.aborts.lock().unwrap().push(upload_id.to_string());
+            self.inner.abort_multipart(path, upload_id).await
+        }
+
+        async fn object_size(&self, path: &str) -> anyhow::Result<Option<u64>> {
+            self.inner.object_size(path).await
+        }
+    }
+
+    fn flaky(failures: Vec<u32>) -> Flaky {
+        Flaky {
+            inner: MemoryStorage::new(None),
+            failures: Mutex::new(failures),
+            uploads: Mutex::default(),
+            aborts: Mutex::default(),
+        }
+    }
+
+    fn config(part_size: usize) -> MultipartConfig {
+        MultipartConfig {
+            part_size,
+            state_dir: std::env::temp_dir().join(format!("uploads-{}", Uuid::new_v4())),
+        }
+    }
+
+    async fn temp_file(data: &[u8]) -> anyhow::Result<PathBuf> {
+        let file = std::env::temp_dir().join(format!("{}.mp3", Uuid::new_v4()));
+        fs::write(&file, data).await?;
+        Ok(file)
+    }
+
+    #[tokio::test]
+    async fn test_upload_in_parts() -> anyhow::Result<()> {
+        let data = (0..25u8).collect::<Vec<_>>();
+        let file = temp_file(&data).await?;
+        let storage = flaky(vec![2]);
+        let config = config(10);
+
+        let uploaded = upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config).await?;
+        assert_eq!(uploaded, digest(&file).await?);
+        assert_eq!(uploaded.size, 25);
+        assert_eq!(*storage.uploads.lock().unwrap(), vec![1, 2, 2, 3]);
+        assert_eq!(storage.inner.get("a.mp3").await?, data);
+        assert!(!fs::try_exists(UploadState::file(&config.state_dir, "a.mp3")).await?);
+        fs::remove_file(file).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_in_parts_resume() -> anyhow::Result<()> {
+        let data = (0..25u8).collect::<Vec<_>>();
+        let file = temp_file(&data).await?;
+        // NOTE: part 3 fails beyond retries
+        let storage = flaky(vec![3; PART_RETRIES as usize + 1]);
+        let config = config(10);
+        assert!(
+            upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config)
+                .await
+                .is_err()
+        );
+        assert!(fs::try_exists(UploadState::file(&config.state_dir, "a.mp3")).await?);
+        fs::remove_file(file).await?;
+
+        // NOTE: the retried task renders the same audio in another work dir
+        let file = temp_file(&data).await?;
+        storage.uploads.lock().unwrap().clear();
+        upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config).await?;
+        assert_eq!(*storage.uploads.lock().unwrap(), vec![3]);
+        assert_eq!(storage.inner.get("a.mp3").await?, data);
+        assert!(storage.aborts.lock().unwrap().is_empty());
+        fs::remove_file(file).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_in_parts_abort_stale() -> anyhow::Result<()> {
+        let data = (0..25u8).collect::<Vec<_>>();
+        let file = temp_file(&data).await?;
+        let storage = flaky(vec![3; PART_RETRIES as usize + 1]);
+        let config = config(10);
+        assert!(
+            upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config)
+                .await
+                .is_err()
+        );
+        let stale = UploadState::load(&config.state_dir, "a.mp3").await.unwrap();
+
+        let config = MultipartConfig {
+            part_size: 20,
+            ..config
+        };
+        upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config).await?;
+        assert_eq!(*storage.aborts.lock().unwrap(), vec![stale.upload_id]);
+        assert_eq!(storage.inner.get("a.mp3").await?, data);
+        fs::remove_file(file).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_in_parts_unknown_upload() -> anyhow::Result<()> {
+        let data = (0..25u8).collect::<Vec<_>>();
+        let file = temp_file(&data).await?;
+        let storage = flaky(vec![]);
+        let config = config(10);
+        // NOTE: e.g. aborted by a lifecycle rule of the bucket
+        let state = UploadState {
+            path: "a.mp3".to_string(),
+            upload_id: "unknown".to_string(),
+            part_size: 10,
+            parts: vec![],
+        };
+        state.save(&config.state_dir).await?;
+
+        upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config).await?;
+        assert_eq!(*storage.uploads.lock().unwrap(), vec![1, 1, 2, 3]);
+        assert_eq!(storage.inner.get("a.mp3").await?, data);
+        assert!(!fs::try_exists(UploadState::file(&config.state_dir, "a.mp3")).await?);
+        fs::remove_file(file).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_empty_file() -> anyhow::Result<()> {
+        let file = temp_file(&[]).await?;
+        let storage = flaky(vec![]);
+        let uploaded = upload_in_parts(&storage, "a.mp3", &file, "audio/mpeg", &config(10)).await?;
+        assert_eq!(uploaded.size, 0);
+        assert_eq!(storage.inner.get("a.mp3").await?, Vec::<u8>::new());
+        fs::remove_file(file).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use super::{
+    join_url,
+    multipart::{
+        upload_in_parts, MultipartConfig, MultipartUpload, UploadNotFound, UploadedFile,
+        UploadedPart,
+    },
+    Storage,
+};
+use anyhow::Context;
+use axum::async_trait;
+use s3::{creds::Credentials, error::S3Error, Bucket, Part, Region};
+use std::{path::Path, time::Duration};
+
+/// Cloudflare R2 or any S3 compatible storage
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+    public_url: Option<String>,
+    multipart: MultipartConfig,
+}
+
+impl S3Storage {
+    fn credentials() -> anyhow::Result<Credentials> {
+        Credentials::from_env()
+            .context("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY are required for the storage")
+    }
+
+    pub fn r2(account_id: &str, bucket: &str, public_url: Option<String>) -> anyhow::Result<Self> {
+        let region = Region::R2 {
+            account_id: account_id.to_string(),
+        };
+        let bucket = Bucket::new(bucket, region, Self::credentials()?)?;
+        Ok(Self {
+            bucket,
+            public_url,
+            multipart: MultipartConfig::from_env(),
+        })
+    }
+
+    pub fn new(
+        endpoint: &str,
+        region: &str,
+        bucket: &str,
+        path_style: bool,
+        public_url: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let region = Region::Custom {
+            region: region.to_string(),
+            endpoint: endpoint.to_string(),
+        };
+        let mut bucket = Bucket::new(bucket, region, Self::credentials()?)?;
+        if path_style {
+            bucket = bucket.with_path_style();
+        }
+        Ok(Self {
+            bucket,
+            public_url,
+            multipart: MultipartConfig::from_env(),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn upload(&self, path: &str, data: &[u8], content_type: &str) -> anyhow::Result<()> {
+        self.bucket
+            .put_object_with_content_type(path, data, content_type)
+            .await?;
+        Ok(())
+    }
+
+    async fn upload_file(
+        &self,
+        path: &str,
+        file: &Path,
+        content_type: &str,
+    ) -> anyhow::Result<UploadedFile> {
+        upload_in_parts(self, path, file, content_type, &self.multipart).await
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let res = self.bucket.get_object(path).await?;
+        Ok(res.to_vec())
+    }
+
+    async fn exists(&self, path: &str) -> anyhow::Result<bool> {
+        match self.bucket.head_object(path).await {
+            Ok(_) => Ok(true),
+            Err(S3Error::HttpFailWithBody(404, _)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        match self.bucket.delete_object(path).await {
+            Ok(_) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let pages = self.bucket.list(prefix.to_string(), None).await?;
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| object.key)
+            .collect())
+    }
+
+    async fn presigned_url(&self, path: &str, expires_in: Duration) -> anyhow::Result<String> {
+        let url = self
+            .bucket
+            .presign_get(path, expires_in.as_secs().try_into()?, None)
+            .await?;
+        Ok(url)
+    }
+
+    fn public_url(&self, path: &str) -> Option<String> {
+        let base = self.public_url.as_deref()?;
+        Some(join_url(base, path))
+    }
+}
+
+#[async_trait]
+impl MultipartUpload for S3Storage {
+    async fn create_multipart(&self, path: &str, content_type: &str) -> anyhow::Result<String> {
+        let res = self
+            .bucket
+            .initiate_multipart_upload(path, content_type)
+            .await?;
+        Ok(res.upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        path: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+        content_type: &str,
+    ) -> anyhow::Result<String> {
+        match self
+            .bucket
+            .put_multipart_chunk(data, path, number, upload_id, content_type)
+            .await
+        {
+            Ok(part) => Ok(part.etag),
+            Err(S3Error::HttpFailWithBody(404, _)) => {
+                Err(UploadNotFound(upload_id.to_string()).into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        let parts = parts
+            .iter()
+            .map(|part| Part {
+                etag: part.etag.clone(),
+                part_number: part.number,
+            })
+            .collect();
+        match self
+            .bucket
+            .complete_multipart_upload(path, upload_id, parts)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(S3Error::HttpFailWithBody(404, _)) => {
+                Err(UploadNotFound(upload_id.to_string()).into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn abort_multipart(&self, path: &str, upload_id: &str) -> anyhow::Result<()> {
+        match self.bucket.abort_upload(path, upload_id).await {
+            Ok(_) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn object_size(&self, path: &str) -> anyhow::Result<Option<u64>> {
+        match self.bucket.head_object(path).await {
+            Ok((head, _)) => Ok(head.content_length.map(|len| len as u64)),
+            Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -1,14 +1,23 @@
-use crate::{error::Error, r2_storage::Storage};
+use super::{
+    feed_service::{chapters_json, FeedService},
+    pronunciation_service::dictionary_for_podcast,
+};
+use crate::{error::Error, storage::Storage};
 use anyhow::Context;
 use api::episode::Section;
 use audio_generator::{
     generate_audio::{generate_audio, SynthesisResult},
+    output::{OutputConfig, OutputProfile, Tags},
+    tts::TtsRegistry,
     workdir::WorkDir,
 };
-use repos::entity::EpisodeId;
-use repos::repo::EpisodeRepo;
-use std::{fs::File, io::Read, sync::Arc};
+use repos::entity::{EpisodeId, Podcast, PodcastId};
+use repos::repo::{EpisodeRepo, PodcastRepo, PronunciationRepo};
+use serde_json::json;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tracing::instrument;
+use uuid::Uuid;
 
 /// evaluated script
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -17,65 +26,232 @@ struct Manuscript {
     sections: Vec<Section>,
 }
 
+/// profiles of the podcast, or a mp3 one when not set
+fn output_profiles(podcast: &Podcast) -> anyhow::Result<Vec<OutputProfile>, Error> {
+    let Some(profiles) = podcast.output_profiles.clone() else {
+        return Ok(vec![OutputProfile::default()]);
+    };
+    let profiles: Vec<OutputProfile> = serde_json::from_value(profiles)
+        .context("Failed to parse output profiles")
+        .map_err(Error::InvalidInput)?;
+    if profiles.is_empty() {
+        return Ok(vec![OutputProfile::default()]);
+    }
+    for profile in profiles.iter() {
+        profile.validate().map_err(Error::InvalidInput)?;
+    }
+    Ok(profiles)
+}
+
 #[derive(Clone)]
 pub(crate) struct EpisodeService {
     episode_repo: Arc<dyn EpisodeRepo>,
+    podcast_repo: Arc<dyn PodcastRepo>,
+    pronunciation_repo: Arc<dyn PronunciationRepo>,
     storage: Arc<dyn Storage>,
+    tts: Arc<TtsRegistry>,
+    feed_service: FeedService,
 }
 
 impl EpisodeService {
-    pub(crate) fn new(episode_repo: Arc<dyn EpisodeRepo>, storage: Arc<dyn Storage>) -> Self {
+    pub(crate) fn new(
+        episode_repo: Arc<dyn EpisodeRepo>,
+        podcast_repo: Arc<dyn PodcastRepo>,
+        pronunciation_repo: Arc<dyn PronunciationRepo>,
+        storage: Arc<dyn Storage>,
+        tts: Arc<TtsRegistry>,
+        feed_service: FeedService,
+    ) -> Self {
         Self {
             episode_repo: episode_repo.clone(),
+            podcast_repo,
+            pronunciation_repo,
             storage,
+            tts,
+            feed_service,
         }
     }
 
-    #[instrument(skip(self, work_dir), ret)]
+    /// the absolute url stored in the episode, podcast apps can not resolve storage paths
+    fn public_url(&self, path: &str) -> anyhow::Result<String, Error> {
+        self.storage
+            .public_url(path)
+            .context("STORAGE_PUBLIC_URL is required for episodes")
+            .map_err(Error::Other)
+    }
+
+    #[instrument(skip(self, work_dir, cancel), ret)]
     pub(crate) async fn generate_audio(
         &self,
         work_dir: &WorkDir,
         episode_id: &EpisodeId,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<(), Error> {
         let mut episode = self.episode_repo.find_by_id(episode_id).await?;
         let sections: Vec<Section> = serde_json::from_value(episode.sections.clone())
             .context("Failed to parse sections")
             .map_err(Error::Other)?;
+        for (i, section) in sections.iter().enumerate() {
+            section
+                .validate()
+                .with_context(|| format!("Invalid section {}", i + 1))
+                .map_err(Error::InvalidInput)?;
+        }
+        let dictionary = dictionary_for_podcast(
+            self.pronunciation_repo.as_ref(),
+            episode.user_id,
+            &PodcastId(episode.podcast_id),
+        )
+        .await?;
+        let podcast = self
+            .podcast_repo
+            .find_by_id(&PodcastId(episode.podcast_id))
+            .await?;
+        // NOTE: fail before the synthesis rather than after it
+        self.public_url(&format!("episodes/{}", episode.id.hyphenated()))?;
+        let output = OutputConfig {
+            profiles: output_profiles(&podcast)?,
+            tags: Tags {
+                title: episode.title.clone(),
+                album: Some(podcast.title.clone()),
+                artwork_url: Some(podcast.icon.clone()).filter(|icon| icon.starts_with("http")),
+            },
+        };
         let SynthesisResult {
-            out_path,
-            srt,
-            duration_sec,
-        } = generate_audio(work_dir, sections)
+            renditions,
+            chapters,
+            transcript,
+            loudness,
+        } = generate_audio(work_dir, sections, &self.tts, &dictionary, &output, cancel)
             .await
             .context("Failed to generate audio")
             .map_err(Error::Other)?;
 
-        episode.duration_sec = Some(duration_sec.round() as i32);
-        let mut file = File::open(&out_path)
-            .context("Failed to open audio file")
+        episode.duration_sec = Some(transcript.duration_sec.round() as i32);
+        if !episode.metadata.is_object() {
+            episode.metadata = json!({});
+        }
+        episode.metadata["loudness"] = serde_json::to_value(loudness)
+            .context("Failed to serialize loudness")
+            .map_err(Error::Other)?;
+        episode.metadata["chapters"] = serde_json::to_value(&chapters)
+            .context("Failed to serialize chapters")
             .map_err(Error::Other)?;
-        let mut audio = vec![];
-        file.read_to_end(&mut audio)
-            .context("Failed to read audio file")
+
+        // NOTE: every object of the episode, objects of former generations are deleted at last
+        let mut paths = vec![];
+        let mut uploaded = vec![];
+        for (i, rendition) in renditions.iter().enumerate() {
+            let profile = &rendition.profile;
+            // NOTE: the primary rendition keeps the plain path
+            let audio_path = if i == 0 {
+                format!(
+                    "episodes/{}.{}",
+                    episode.id.hyphenated(),
+                    profile.format.extension()
+                )
+            } else {
+                format!(
+                    "episodes/{}.{}.{}",
+                    episode.id.hyphenated(),
+                    profile.name(),
+                    profile.format.extension()
+                )
+            };
+            let file = self
+                .storage
+                .upload_file(&audio_path, &rendition.path, profile.format.mime_type())
+                .await
+                .context("Failed to upload audio")
+                .map_err(Error::Other)?;
+            uploaded.push(json!({
+                "format": profile.format,
+                "bitrateKbps": profile.bitrate_kbps,
+                "channels": profile.channels,
+                "mimeType": profile.format.mime_type(),
+                "sizeBytes": file.size,
+                "sha256": file.sha256,
+                "url": self.public_url(&audio_path)?,
+            }));
+            if i == 0 {
+                episode.audio_url = Some(self.public_url(&audio_path)?);
+            }
+            paths.push(audio_path);
+        }
+        episode.metadata["renditions"] = json!(uploaded);
+
+        let srt_path = format!("episodes/{}.srt", episode.id.hyphenated());
+        self.storage
+            .upload(&srt_path, transcript.srt().as_bytes(), "text/plain")
+            .await
+            .context("Failed to upload srt")
             .map_err(Error::Other)?;
+        episode.srt_url = Some(self.public_url(&srt_path)?);
+        paths.push(srt_path);
 
-        let audio_path = format!("episodes/{}.mp3", episode.id.hyphenated());
+        let vtt_path = format!("episodes/{}.vtt", episode.id.hyphenated());
         self.storage
-            .upload(&audio_path, &audio, "audio/mp3")
+            .upload(&vtt_path, transcript.webvtt().as_bytes(), "text/vtt")
             .await
-            .context("Failed to upload audio")
+            .context("Failed to upload vtt")
             .map_err(Error::Other)?;
-        episode.audio_url = Some(audio_path);
+        episode.vtt_url = Some(self.public_url(&vtt_path)?);
+        paths.push(vtt_path);
 
-        let srt_path = format!("episodes/{}.srt", episode.id.hyphenated());
+        let transcript_path = format!("episodes/{}.transcript.json", episode.id.hyphenated());
+        let transcript = serde_json::to_vec(&transcript)
+            .context("Failed to serialize transcript")
+            .map_err(Error::Other)?;
         self.storage
-            .upload(&srt_path, srt.as_bytes(), "text/plain")
+            .upload(&transcript_path, &transcript, "application/json")
             .await
-            .context("Failed to upload srt")
+            .context("Failed to upload transcript")
             .map_err(Error::Other)?;
-        episode.srt_url = Some(srt_path);
+        episode.transcript_url = Some(self.public_url(&transcript_path)?);
+        paths.push(transcript_path);
+
+        if !chapters.is_empty() {
+            let chapters_path = format!("episodes/{}.chapters.json", episode.id.hyphenated());
+            let chapters = serde_json::to_vec(&chapters_json(&chapters))
+                .context("Failed to serialize chapters")
+                .map_err(Error::Other)?;
+            self.storage
+                .upload(&chapters_path, &chapters, "application/json+chapters")
+                .await
+                .context("Failed to upload chapters")
+                .map_err(Error::Other)?;
+            episode.metadata["chaptersUrl"] = json!(self.public_url(&chapters_path)?);
+            paths.push(chapters_path);
+        }
 
         self.episode_repo.update(&episode).await?;
+        self.delete_stale_objects(&episode.id, &paths).await;
+        // NOTE: the episode is ready even if its feed is stale until the next episode
+        if let Err(e) = self
+            .feed_service
+            .publish(&PodcastId(episode.podcast_id))
+            .await
+        {
+            tracing::warn!("Failed to publish feed of {}: {:?}", episode.podcast_id, e);
+        }
         Ok(())
     }
+
+    /// deletes objects of the episode not in `paths`, e.g. renditions of removed profiles.
+    /// failures are only logged since the episode is already updated
+    async fn delete_stale_objects(&self, episode_id: &Uuid, paths: &[String]) {
+        let prefix = format!("episodes/{}.", episode_id.hyphenated());
+        let existing = match self.storage.list(&prefix).await {
+            Ok(existing) => existing,
+            Err(e) => {
+                tracing::warn!("Failed to list objects of episode {}: {:?}", episode_id, e);
+                return;
+            }
+        };
+        for path in existing.iter().filter(|path| !paths.contains(path)) {
+            if let Err(e) = self.storage.delete(path).await {
+                tracing::warn!("Failed to delete {}: {:?}", path, e);
+            }
+        }
+    }
 }
@@ -0,0 +1,460 @@
+use crate::{
+    error::Error,
+    storage::{join_url, Storage},
+};
+use anyhow::Context;
+use audio_generator::output::Chapter;
+use repos::entity::{Episode, Podcast, PodcastId};
+use repos::repo::{EpisodeRepo, PodcastRepo};
+use rss::{
+    extension::{
+        itunes::{ITunesCategory, ITunesChannelExtension, ITunesItemExtension, ITunesOwner},
+        Extension, ExtensionMap,
+    },
+    Channel, Enclosure, Guid, Item,
+};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::instrument;
+use uuid::{uuid, Uuid};
+
+const PODCAST_NAMESPACE: &str = "https://podcastindex.org/namespace/1.0";
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+/// namespace of `podcast:guid` by the podcast namespace
+const PODCAST_GUID_NAMESPACE: Uuid = uuid!("ead4c236-bf58-58c6-a2c6-a6b28d128cb6");
+
+/// channel fields which podcasts do not have, shared by feeds of the deployment
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FeedConfig {
+    /// `SITE_URL`, podcasts are linked to their pages `{SITE_URL}/podcasts/{id}`
+    pub(crate) site_url: Option<String>,
+    /// `FEED_AUTHOR`, the title of the podcast by default
+    pub(crate) author: Option<String>,
+    /// `FEED_OWNER_NAME` and `FEED_OWNER_EMAIL`, podcast directories verify owners by the email
+    pub(crate) owner_name: Option<String>,
+    pub(crate) owner_email: Option<String>,
+    /// `FEED_CATEGORY`, one of the apple podcasts categories
+    pub(crate) category: String,
+    /// `FEED_LANGUAGE`, of the channel and transcripts since podcasts do not have one
+    pub(crate) language: String,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            site_url: None,
+            author: None,
+            owner_name: None,
+            owner_email: None,
+            category: "News".to_string(),
+            language: "ja".to_string(),
+        }
+    }
+}
+
+impl FeedConfig {
+    pub(crate) fn from_env() -> Self {
+        // NOTE: compose passes unset variables as empty strings
+        let var = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+        Self {
+            site_url: var("SITE_URL"),
+            author: var("FEED_AUTHOR"),
+            owner_name: var("FEED_OWNER_NAME"),
+            owner_email: var("FEED_OWNER_EMAIL"),
+            category: var("FEED_CATEGORY").unwrap_or(Self::default().category),
+            language: var("FEED_LANGUAGE").unwrap_or(Self::default().language),
+        }
+    }
+}
+
+/// where the feed of a podcast is published in the storage
+pub(crate) fn feed_path(podcast_id: &Uuid) -> String {
+    format!("podcasts/{}/feed.xml", podcast_id.hyphenated())
+}
+
+/// chapters in the json chapters format of the podcast namespace
+pub(crate) fn chapters_json(chapters: &[Chapter]) -> Value {
+    let chapters = chapters
+        .iter()
+        .map(|chapter| {
+            json!({
+                "startTime": chapter.start_sec,
+                "endTime": chapter.end_sec,
+                "title": chapter.title,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "version": "1.2.0",
+        "chapters": chapters,
+    })
+}
+
+/// `podcast:guid`, the uuid v5 of the feed url without its scheme and trailing slashes
+fn podcast_guid(feed_url: &str) -> Uuid {
+    let url = feed_url.split_once("://").map_or(feed_url, |(_, url)| url);
+    Uuid::new_v5(
+        &PODCAST_GUID_NAMESPACE,
+        url.trim_end_matches('/').as_bytes(),
+    )
+}
+
+/// `HH:MM:SS` of `itunes:duration`
+fn itunes_duration(sec: i32) -> String {
+    format!("{:02}:{:02}:{:02}", sec / 3600, sec / 60 % 60, sec % 60)
+}
+
+fn podcast_tag(name: &str, attrs: &[(&str, &str)]) -> Extension {
+    Extension {
+        name: format!("podcast:{}", name),
+        attrs: attrs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// an absolute url, storage paths of episodes generated without `STORAGE_PUBLIC_URL` are resolved
+fn absolute_url(url: &str, public_url: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Some(url.to_string());
+    }
+    public_url(url)
+}
+
+/// an item of an episode with audio
+fn item(
+    episode: &Episode,
+    language: &str,
+    public_url: &impl Fn(&str) -> Option<String>,
+) -> Option<Item> {
+    let audio_url = absolute_url(episode.audio_url.as_ref()?, public_url)?;
+    // NOTE: the first rendition is the primary audio
+    let primary = &episode.metadata["renditions"][0];
+    let enclosure = Enclosure {
+        url: audio_url,
+        length: primary["sizeBytes"].as_u64().unwrap_or(0).to_string(),
+        mime_type: primary["mimeType"]
+            .as_str()
+            .unwrap_or("audio/mpeg")
+            .to_string(),
+    };
+
+    let mut podcast = BTreeMap::new();
+    let transcripts = [
+        (&episode.vtt_url, "text/vtt"),
+        (&episode.srt_url, "application/x-subrip"),
+    ]
+    .into_iter()
+    .filter_map(|(url, mime_type)| {
+        let url = absolute_url(url.as_ref()?, public_url)?;
+        Some(podcast_tag(
+            "transcript",
+            &[("url", &url), ("type", mime_type), ("language", language)],
+        ))
+    })
+    .collect::<Vec<_>>();
+    if !transcripts.is_empty() {
+        podcast.insert("transcript".to_string(), transcripts);
+    }
+    if let Some(url) = episode.metadata["chaptersUrl"]
+        .as_str()
+        .and_then(|url| absolute_url(url, public_url))
+    {
+        let chapters = podcast_tag(
+            "chapters",
+            &[("url", &url), ("type", "application/json+chapters")],
+        );
+        podcast.insert("chapters".to_string(), vec![chapters]);
+    }
+    let mut extensions = ExtensionMap::new();
+    if !podcast.is_empty() {
+        extensions.insert("podcast".to_string(), podcast);
+    }
+
+    let mut itunes = ITunesItemExtension::default();
+    itunes.set_duration(episode.duration_sec.map(itunes_duration));
+    itunes.set_episode_type("full".to_string());
+
+    let mut item = Item::default();
+    item.set_title(episode.title.clone());
+    item.set_description(episode.description.clone());
+    item.set_guid(Guid {
+        value: episode.id.hyphenated().to_string(),
+        permalink: false,
+    });
+    item.set_pub_date(episode.created_at.to_rfc2822());
+    item.set_enclosure(enclosure);
+    item.set_itunes_ext(itunes);
+    item.set_extensions(extensions);
+    Some(item)
+}
+
+/// rss 2.0 with the itunes and podcast namespaces, of episodes with audio newest first
+pub(crate) fn build_feed(
+    podcast: &Podcast,
+    episodes: &[Episode],
+    feed_url: &str,
+    config: &FeedConfig,
+    public_url: impl Fn(&str) -> Option<String>,
+) -> Channel {
+    let mut episodes = episodes.iter().collect::<Vec<_>>();
+    episodes.sort_by_key(|episode| std::cmp::Reverse(episode.created_at));
+    let items = episodes
+        .into_iter()
+        .filter_map(|episode| item(episode, &config.language, &public_url))
+        .collect::<Vec<_>>();
+    let description = podcast.description.clone().unwrap_or(podcast.title.clone());
+
+    let mut itunes = ITunesChannelExtension::default();
+    itunes.set_image(Some(podcast.icon.clone()).filter(|icon| icon.starts_with("http")));
+    itunes.set_summary(description.clone());
+    itunes.set_explicit("false".to_string());
+    itunes.set_author(config.author.clone().unwrap_or(podcast.title.clone()));
+    if config.owner_name.is_some() || config.owner_email.is_some() {
+        itunes.set_owner(ITunesOwner {
+            name: config.owner_name.clone(),
+            email: config.owner_email.clone(),
+        });
+    }
+    itunes.set_categories(vec![ITunesCategory {
+        text: config.category.clone(),
+        subcategory: None,
+    }]);
+
+    let mut extensions = ExtensionMap::new();
+    let self_link = Extension {
+        name: "atom:link".to_string(),
+        attrs: BTreeMap::from([
+            ("href".to_string(), feed_url.to_string()),
+            ("rel".to_string(), "self".to_string()),
+            ("type".to_string(), "application/rss+xml".to_string()),
+        ]),
+        ..Default::default()
+    };
+    extensions.insert(
+        "atom".to_string(),
+        BTreeMap::from([("link".to_string(), vec![self_link])]),
+    );
+    let guid = Extension {
+        name: "podcast:guid".to_string(),
+        value: Some(podcast_guid(feed_url).hyphenated().to_string()),
+        ..Default::default()
+    };
+    extensions.insert(
+        "podcast".to_string(),
+        BTreeMap::from([("guid".to_string(), vec![guid])]),
+    );
+
+    // NOTE: without a site, the feed is the only page of the podcast
+    let link = match &config.site_url {
+        Some(site_url) => join_url(site_url, &format!("podcasts/{}", podcast.id.hyphenated())),
+        None => feed_url.to_string(),
+    };
+    let mut channel = Channel::default();
+    channel.set_title(podcast.title.clone());
+    channel.set_link(link);
+    channel.set_description(description);
+    channel.set_language(config.language.clone());
+    channel.set_generator("botcast".to_string());
+    channel.set_last_build_date(
+        items
+            .first()
+            .and_then(|item| item.pub_date.clone())
+            .unwrap_or(podcast.created_at.to_rfc2822()),
+    );
+    channel.set_itunes_ext(itunes);
+    channel.set_extensions(extensions);
+    channel.set_namespaces(BTreeMap::from([
+        ("podcast".to_string(), PODCAST_NAMESPACE.to_string()),
+        ("atom".to_string(), ATOM_NAMESPACE.to_string()),
+    ]));
+    channel.set_items(items);
+    channel
+}
+
+#[derive(Clone)]
+pub(crate) struct FeedService {
+    podcast_repo: Arc<dyn PodcastRepo>,
+    episode_repo: Arc<dyn EpisodeRepo>,
+    storage: Arc<dyn Storage>,
+    config: FeedConfig,
+}
+
+impl FeedService {
+    pub(crate) fn new(
+        podcast_repo: Arc<dyn PodcastRepo>,
+        episode_repo: Arc<dyn EpisodeRepo>,
+        storage: Arc<dyn Storage>,
+    ) -> Self {
+        Self {
+            podcast_repo,
+            episode_repo,
+            storage,
+            config: FeedConfig::from_env(),
+        }
+    }
+
+    /// the feed xml of a podcast
+    #[instrument(skip(self))]
+    pub(crate) async fn feed(&self, podcast_id: &PodcastId) -> anyhow::Result<String, Error> {
+        let podcast = self.podcast_repo.find_by_id(podcast_id).await?;
+        let episodes = self.episode_repo.find_all_by_podcast_id(podcast_id).await?;
+        // NOTE: podcast apps can not resolve storage paths
+        let feed_url = self.public_url(&feed_path(&podcast.id))?;
+        let channel = build_feed(&podcast, &episodes, &feed_url, &self.config, |path| {
+            self.storage.public_url(path)
+        });
+        Ok(channel.to_string())
+    }
+
+    /// regenerates the feed in the storage, returns its url
+    #[instrument(skip(self))]
+    pub(crate) async fn publish(&self, podcast_id: &PodcastId) -> anyhow::Result<String, Error> {
+        let feed = self.feed(podcast_id).await?;
+        let path = feed_path(&podcast_id.0);
+        self.storage
+            .upload(&path, feed.as_bytes(), "application/rss+xml")
+            .await
+            .context("Failed to upload feed")
+            .map_err(Error::Other)?;
+        self.public_url(&path)
+    }
+
+    fn public_url(&self, path: &str) -> anyhow::Result<String, Error> {
+        self.storage
+            .public_url(path)
+            .context("STORAGE_PUBLIC_URL is required for feeds")
+            .map_err(Error::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn episode(title: &str, day: u32, audio_url: Option<&str>) -> Episode {
+        Episode {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            description: Some(format!("{} description", title)),
+            audio_url: audio_url.map(ToString::to_string),
+            duration_sec: Some(3723),
+            sections: json!([]),
+            srt_url: audio_url.map(|_| "https://cdn.example.com/episodes/a.srt".to_string()),
+            podcast_id: Uuid::nil(),
+            user_id: None,
+            created_at: Utc.with_ymd_and_hms(2026, 10, day, 0, 0, 0).unwrap(),
+            metadata: json!({
+                "renditions": [{"mimeType": "audio/mp4", "sizeBytes": 1234}],
+                "chaptersUrl": "https://cdn.example.com/episodes/a.chapters.json",
+            }),
+            // NOTE: a storage path of an episode generated without `STORAGE_PUBLIC_URL`
+            vtt_url: audio_url.map(|_| "episodes/a.vtt".to_string()),
+            transcript_url: None,
+        }
+    }
+
+    #[test]
+    fn test_build_feed() {
+        let podcast = Podcast {
+            id: Uuid::nil(),
+            title: "ニュース & 雑談".to_string(),
+            description: None,
+            icon: "https://cdn.example.com/icon.png".to_string(),
+            user_id: None,
+            created_at: Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap(),
+            output_profiles: None,
+        };
+        let episodes = [
+            episode("first", 1, Some("https://cdn.example.com/episodes/a.m4a")),
+            episode("draft", 3, None),
+            episode("second", 2, Some("episodes/b.m4a")),
+        ];
+        let config = FeedConfig {
+            site_url: Some("https://botcast.example.com".to_string()),
+            owner_email: Some("owner@example.com".to_string()),
+            ..Default::default()
+        };
+        let channel = build_feed(
+            &podcast,
+            &episodes,
+            "https://cdn.example.com/feed.xml",
+            &config,
+            |path| Some(format!("https://cdn.example.com/{}", path)),
+        );
+        assert_eq!(
+            channel.link(),
+            "https://botcast.example.com/podcasts/00000000-0000-0000-0000-000000000000"
+        );
+
+        let titles = channel
+            .items()
+            .iter()
+            .map(|item| item.title().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["second", "first"]);
+        assert_eq!(channel.last_build_date(), channel.items()[0].pub_date());
+
+        let item = &channel.items()[1];
+        let enclosure = item.enclosure().unwrap();
+        assert_eq!(enclosure.url, "https://cdn.example.com/episodes/a.m4a");
+        assert_eq!(enclosure.length, "1234");
+        assert_eq!(enclosure.mime_type, "audio/mp4");
+        assert_eq!(item.itunes_ext().unwrap().duration(), Some("01:02:03"));
+        let transcripts = &item.extensions()["podcast"]["transcript"];
+        assert_eq!(
+            transcripts[0].attrs["url"],
+            "https://cdn.example.com/episodes/a.vtt"
+        );
+        assert_eq!(
+            transcripts[1].attrs["url"],
+            "https://cdn.example.com/episodes/a.srt"
+        );
+        assert_eq!(
+            channel.items()[0].enclosure().unwrap().url,
+            "https://cdn.example.com/episodes/b.m4a"
+        );
+
+        let xml = channel.to_string();
+        assert!(xml.contains(r#"xmlns:podcast="https://podcastindex.org/namespace/1.0""#));
+        assert!(xml.contains("xmlns:itunes="));
+        assert!(xml.contains("<language>ja</language>"));
+        assert!(xml.contains("ニュース &amp; 雑談"));
+        assert!(xml.contains(r#"<atom:link href="https://cdn.example.com/feed.xml" rel="self" type="application/rss+xml""#));
+        assert!(xml.contains(r#"<itunes:category text="News">"#));
+        assert!(xml.contains("<itunes:email>owner@example.com</itunes:email>"));
+        assert!(xml.contains("<itunes:author>ニュース &amp; 雑談</itunes:author>"));
+        assert!(xml.contains("<podcast:guid>"));
+        assert!(xml.contains(r#"<podcast:chapters type="application/json+chapters" url="https://cdn.example.com/episodes/a.chapters.json"#));
+        assert!(xml.contains(r#"<podcast:transcript language="ja" type="text/vtt""#));
+        assert!(Channel::read_from(xml.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_podcast_guid() {
+        // NOTE: the example of the podcast namespace
+        assert_eq!(
+            podcast_guid("https://mp3s.nashownotes.com/pc20rss.xml").to_string(),
+            "917393e3-1b1e-5cef-ace4-edaa54e1f810"
+        );
+    }
+
+    #[test]
+    fn test_chapters_json() {
+        let chapters = [Chapter {
+            title: "オープニング".to_string(),
+            start_sec: 0.0,
+            end_sec: 12.5,
+        }];
+        assert_eq!(
+            chapters_json(&chapters),
+            json!({
+                "version": "1.2.0",
+                "chapters": [{"startTime": 0.0, "endTime": 12.5, "title": "オープニング"}],
+            })
+        );
+    }
+}
@@ -1,11 +1,35 @@
 pub(crate) mod episode_service;
+pub(crate) mod feed_service;
+pub(crate) mod pipeline;
+pub(crate) mod pronunciation_service;
 pub(crate) mod provider;
+pub(crate) mod retry_policy;
+pub(crate) mod schedule_service;
 pub(crate) mod script_service;
 pub(crate) mod task_service;
 
+use crate::error::Error;
+use anyhow::Context;
 use api::client::ApiClient;
+use audio_generator::tts::TtsRegistry;
 pub use provider::Provider;
 use std::{fmt::Debug, sync::Arc};
+use uuid::Uuid;
+
+/// id of the user authorized by the api client
+pub(crate) async fn current_user_id(api_client: &ApiClient) -> anyhow::Result<Uuid, Error> {
+    let user = api_client
+        .me()
+        .await
+        .context("Failed to get user")
+        .map_err(Error::Other)?;
+    let user_id = user
+        .id
+        .parse()
+        .context("Failed to parse user id")
+        .map_err(Error::Other)?;
+    Ok(user_id)
+}
 
 pub(crate) trait ProvideApiClient: Debug + Send + Sync {
     fn api_client(&self) -> Arc<ApiClient>;
@@ -34,3 +58,25 @@ impl ProvideApiClient for UserApiClientProvider {
         ))
     }
 }
+
+pub(crate) trait ProvideTts: Debug + Send + Sync {
+    fn tts(&self) -> Arc<TtsRegistry>;
+}
+
+/// shares engines built at startup, so their concurrency is bounded across tasks
+#[derive(Debug, Clone)]
+pub(crate) struct TtsProvider {
+    tts: Arc<TtsRegistry>,
+}
+
+impl TtsProvider {
+    pub(crate) fn new(tts: Arc<TtsRegistry>) -> Self {
+        Self { tts }
+    }
+}
+
+impl ProvideTts for TtsProvider {
+    fn tts(&self) -> Arc<TtsRegistry> {
+        self.tts.clone()
+    }
+}
@@ -0,0 +1,72 @@
+use crate::error::Error;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// a step of `Args::Pipeline`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Step {
+    /// `Args` of the step, may lack fields bound from `inputs`
+    #[serde(flatten)]
+    pub(crate) args: Map<String, Value>,
+    /// JSON pointer into the args -> JSON pointer into the results of previous steps,
+    /// e.g. `{ "/episodeId": "/0/id" }`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) inputs: BTreeMap<String, String>,
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+impl Step {
+    /// args with the results of previous steps bound
+    pub(crate) fn bind(&self, results: &[Value]) -> anyhow::Result<Value, Error> {
+        let results = Value::Array(results.to_vec());
+        let mut args = Value::Object(self.args.clone());
+        for (target, source) in self.inputs.iter() {
+            let value = results.pointer(source).ok_or_else(|| {
+                Error::InvalidInput(anyhow::anyhow!("{} is not found in step results", source))
+            })?;
+            let (parent, key) = target.rsplit_once('/').ok_or_else(|| {
+                Error::InvalidInput(anyhow::anyhow!("{} is not a JSON pointer", target))
+            })?;
+            let Some(Value::Object(parent)) = args.pointer_mut(parent) else {
+                return Err(Error::InvalidInput(anyhow::anyhow!(
+                    "parent of {} is not an object",
+                    target
+                )));
+            };
+            parent.insert(unescape(key), value.clone());
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_bind() -> anyhow::Result<()> {
+        let step: Step = serde_json::from_value(json!({
+            "type": "evaluateTemplate",
+            "template": "${episode.title}",
+            "parameters": {},
+            "inputs": {
+                "/parameters/episode": "/0",
+            },
+        }))?;
+        let args = step.bind(&[json!({ "id": "x", "title": "hello" })])?;
+        assert_eq!(
+            args,
+            json!({
+                "type": "evaluateTemplate",
+                "template": "${episode.title}",
+                "parameters": { "episode": { "id": "x", "title": "hello" } },
+            })
+        );
+        assert!(step.bind(&[]).is_err());
+        Ok(())
+    }
+}
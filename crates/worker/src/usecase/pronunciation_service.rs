@@ -0,0 +1,147 @@
+use super::current_user_id;
+use crate::error::Error;
+use api::client::ApiClient;
+use audio_generator::dictionary::Dictionary;
+use chrono::Utc;
+use repos::{
+    entity::{PodcastId, Pronunciation, PronunciationId},
+    repo::{PodcastRepo, PronunciationRepo},
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PronunciationInput {
+    /// `None` applies to all podcasts of the user
+    pub(crate) podcast_id: Option<Uuid>,
+    pub(crate) surface: String,
+    pub(crate) reading: String,
+}
+
+fn is_katakana(c: char) -> bool {
+    matches!(c, 'ァ'..='ヴ' | 'ー')
+}
+
+impl PronunciationInput {
+    fn validate(&self) -> anyhow::Result<(), Error> {
+        if self.surface.trim().is_empty() {
+            return Err(Error::InvalidInput(anyhow::anyhow!("surface is empty")));
+        }
+        if self.reading.is_empty() || !self.reading.chars().all(is_katakana) {
+            return Err(Error::InvalidInput(anyhow::anyhow!(
+                "reading must be katakana: {}",
+                self.reading
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// dictionary of readings applied to serifs before synthesis
+#[derive(Clone)]
+pub(crate) struct PronunciationService {
+    pronunciation_repo: Arc<dyn PronunciationRepo>,
+    podcast_repo: Arc<dyn PodcastRepo>,
+    api_client: Arc<ApiClient>,
+}
+
+impl PronunciationService {
+    pub(crate) fn new(
+        pronunciation_repo: Arc<dyn PronunciationRepo>,
+        podcast_repo: Arc<dyn PodcastRepo>,
+        api_client: Arc<ApiClient>,
+    ) -> Self {
+        Self {
+            pronunciation_repo,
+            podcast_repo,
+            api_client,
+        }
+    }
+
+    /// podcasts of other users are treated as not found
+    async fn check_podcast(&self, user_id: &Uuid, podcast_id: &Uuid) -> anyhow::Result<(), Error> {
+        let podcast = self
+            .podcast_repo
+            .find_by_id(&PodcastId(*podcast_id))
+            .await?;
+        if podcast.user_id != Some(*user_id) {
+            return Err(Error::Repo(repos::error::Error::NotFound(
+                "podcast".to_string(),
+                podcast_id.to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// all entries of the user, or entries effective for the podcast
+    pub(crate) async fn list_pronunciations(
+        &self,
+        podcast_id: Option<Uuid>,
+    ) -> anyhow::Result<Vec<Pronunciation>, Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        match podcast_id {
+            Some(podcast_id) => {
+                self.check_podcast(&user_id, &podcast_id).await?;
+                Ok(self
+                    .pronunciation_repo
+                    .find_for_podcast(&user_id, &PodcastId(podcast_id))
+                    .await?)
+            }
+            None => Ok(self.pronunciation_repo.find_by_user(&user_id).await?),
+        }
+    }
+
+    pub(crate) async fn upsert_pronunciation(
+        &self,
+        input: PronunciationInput,
+    ) -> anyhow::Result<Pronunciation, Error> {
+        input.validate()?;
+        let user_id = current_user_id(&self.api_client).await?;
+        if let Some(podcast_id) = input.podcast_id.as_ref() {
+            self.check_podcast(&user_id, podcast_id).await?;
+        }
+        let pronunciation = Pronunciation {
+            id: Uuid::new_v4(),
+            user_id,
+            podcast_id: input.podcast_id,
+            surface: input.surface.trim().to_string(),
+            reading: input.reading,
+            created_at: Utc::now(),
+        };
+        Ok(self.pronunciation_repo.upsert(&pronunciation).await?)
+    }
+
+    pub(crate) async fn delete_pronunciation(
+        &self,
+        id: &PronunciationId,
+    ) -> anyhow::Result<(), Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        let pronunciation = self.pronunciation_repo.find_by_id(id).await?;
+        if pronunciation.user_id != user_id {
+            return Err(Error::Repo(repos::error::Error::NotFound(
+                "pronunciation".to_string(),
+                id.0.to_string(),
+            )));
+        }
+        self.pronunciation_repo.delete(id).await?;
+        Ok(())
+    }
+}
+
+/// podcast scoped entries override user scoped ones of the same surface
+pub(crate) async fn dictionary_for_podcast(
+    pronunciation_repo: &dyn PronunciationRepo,
+    user_id: Option<Uuid>,
+    podcast_id: &PodcastId,
+) -> anyhow::Result<Dictionary, Error> {
+    let Some(user_id) = user_id else {
+        return Ok(Dictionary::default());
+    };
+    let pronunciations = pronunciation_repo
+        .find_for_podcast(&user_id, podcast_id)
+        .await?;
+    Ok(Dictionary::new(pronunciations.into_iter().map(
+        |pronunciation| (pronunciation.surface, pronunciation.reading),
+    )))
+}
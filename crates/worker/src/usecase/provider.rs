@@ -1,8 +1,11 @@
 use super::{
-    episode_service::EpisodeService, script_service::ScriptService, task_service::TaskService,
-    ProvideApiClient, UserApiClientProvider,
+    episode_service::EpisodeService, feed_service::FeedService,
+    pronunciation_service::PronunciationService, schedule_service::ScheduleService,
+    script_service::ScriptService, task_service::TaskService, ProvideApiClient, ProvideTts,
+    TtsProvider, UserApiClientProvider,
 };
-use crate::r2_storage::ProvideStorage;
+use crate::storage::{ProvideStorage, Storage, StorageProvider};
+use audio_generator::tts::TtsRegistry;
 use repos::provider::*;
 use std::sync::Arc;
 
@@ -11,27 +14,32 @@ pub struct Provider {
     pub(crate) provide_podcast_repo: Arc<dyn ProvidePodcastRepo>,
     pub(crate) provide_episode_repo: Arc<dyn ProvideEpisodeRepo>,
     pub(crate) provide_task_repo: Arc<dyn ProvideTaskRepo>,
+    pub(crate) provide_schedule_repo: Arc<dyn ProvideScheduleRepo>,
     pub(crate) provide_script_repo: Arc<dyn ProvideScriptRepo>,
+    pub(crate) provide_pronunciation_repo: Arc<dyn ProvidePronunciationRepo>,
     pub(crate) provide_storage: Arc<dyn ProvideStorage>,
+    pub(crate) provide_tts: Arc<dyn ProvideTts>,
     pub(crate) provide_secret_repo: Arc<dyn ProvideSecretRepo>,
     pub(crate) provide_api_client: Arc<dyn ProvideApiClient>,
 }
 
-impl Default for Provider {
-    fn default() -> Self {
+impl Provider {
+    /// repos from the database, and the storage and tts engines built from their configs
+    pub fn new(storage: Arc<dyn Storage>, tts: Arc<TtsRegistry>) -> Self {
         Self {
             provide_podcast_repo: Arc::new(DefaultProvider),
             provide_episode_repo: Arc::new(DefaultProvider),
             provide_task_repo: Arc::new(DefaultProvider),
+            provide_schedule_repo: Arc::new(DefaultProvider),
             provide_script_repo: Arc::new(DefaultProvider),
-            provide_storage: Arc::new(DefaultProvider),
+            provide_pronunciation_repo: Arc::new(DefaultProvider),
+            provide_storage: Arc::new(StorageProvider::new(storage)),
+            provide_tts: Arc::new(TtsProvider::new(tts)),
             provide_secret_repo: Arc::new(DefaultProvider),
             provide_api_client: Arc::new(UserApiClientProvider::default()),
         }
     }
-}
 
-impl Provider {
     pub(crate) fn task_service(&self) -> TaskService {
         TaskService::new(
             self.provide_task_repo.task_repo(),
@@ -41,10 +49,38 @@ impl Provider {
         )
     }
 
+    pub(crate) fn schedule_service(&self) -> ScheduleService {
+        ScheduleService::new(
+            self.provide_schedule_repo.schedule_repo(),
+            self.provide_task_repo.task_repo(),
+            self.provide_api_client.api_client(),
+        )
+    }
+
     pub(crate) fn episode_service(&self) -> EpisodeService {
         EpisodeService::new(
             self.provide_episode_repo.episode_repo(),
+            self.provide_podcast_repo.podcast_repo(),
+            self.provide_pronunciation_repo.pronunciation_repo(),
             self.provide_storage.storage(),
+            self.provide_tts.tts(),
+            self.feed_service(),
+        )
+    }
+
+    pub(crate) fn feed_service(&self) -> FeedService {
+        FeedService::new(
+            self.provide_podcast_repo.podcast_repo(),
+            self.provide_episode_repo.episode_repo(),
+            self.provide_storage.storage(),
+        )
+    }
+
+    pub(crate) fn pronunciation_service(&self) -> PronunciationService {
+        PronunciationService::new(
+            self.provide_pronunciation_repo.pronunciation_repo(),
+            self.provide_podcast_repo.podcast_repo(),
+            self.provide_api_client.api_client(),
         )
     }
 
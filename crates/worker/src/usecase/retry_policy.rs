@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use std::time::Duration;
+
+/// retries are opt-in per task, `{}` retries with the default policy
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct RetryPolicy {
+    /// includes the first attempt
+    pub(crate) max_attempts: u32,
+    pub(crate) base_delay_sec: u64,
+    pub(crate) max_delay_sec: u64,
+    /// ratio of random spread applied to each delay, in `0.0..=1.0`
+    pub(crate) jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_sec: 30,
+            max_delay_sec: 60 * 60,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// delay before the next attempt, or `None` if `attempts` reached the limit
+    pub(crate) fn next_delay(&self, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let exp = 2u64.saturating_pow(attempts.saturating_sub(1));
+        let delay = self
+            .base_delay_sec
+            .saturating_mul(exp)
+            .min(self.max_delay_sec) as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let spread = if jitter > 0.0 {
+            rand::thread_rng().gen_range(-jitter..=jitter)
+        } else {
+            0.0
+        };
+        Some(Duration::from_secs_f64(delay * (1.0 + spread)))
+    }
+}
+
+/// failed attempt recorded in `tasks.errors`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Attempt {
+    pub(crate) attempt: u32,
+    pub(crate) executed_at: Option<DateTime<Utc>>,
+    pub(crate) finished_at: DateTime<Utc>,
+    pub(crate) error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_delay_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay_sec: 10,
+            max_delay_sec: 30,
+            jitter: 0.0,
+        };
+        assert_eq!(policy.next_delay(1), Some(Duration::from_secs(10)));
+        assert_eq!(policy.next_delay(2), Some(Duration::from_secs(20)));
+        assert_eq!(policy.next_delay(3), Some(Duration::from_secs(30)));
+        assert_eq!(policy.next_delay(4), Some(Duration::from_secs(30)));
+        assert_eq!(policy.next_delay(5), None);
+    }
+
+    #[test]
+    fn test_next_delay_jitter() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..Default::default()
+        };
+        let delay = policy.next_delay(1).unwrap();
+        assert!(delay >= Duration::from_secs(15) && delay <= Duration::from_secs(45));
+    }
+
+    #[test]
+    fn test_empty_policy() {
+        let policy: RetryPolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(policy, RetryPolicy::default());
+    }
+}
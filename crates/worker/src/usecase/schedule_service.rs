@@ -0,0 +1,327 @@
+use super::current_user_id;
+use super::retry_policy::RetryPolicy;
+use super::task_service::{new_task, Args};
+use crate::error::Error;
+use anyhow::Context;
+use api::client::ApiClient;
+use chrono::{DateTime, LocalResult, NaiveDateTime, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use repos::entity::{MissedFirePolicy, Schedule, ScheduleId};
+use repos::repo::{ScheduleRepo, TaskRepo};
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// fires older than this are dropped by `MissedFirePolicy::Skip`
+const MISFIRE_GRACE: TimeDelta = TimeDelta::minutes(1);
+/// upper bound of fires materialized from a schedule at once
+const MAX_FIRES: usize = 100;
+
+/// upper bound of wall clock candidates examined for a fire
+const MAX_CANDIDATES: usize = 10_000;
+
+/// resolves a wall clock time in `tz`.
+/// a repeated time is the first occurrence, a time skipped by DST is shifted forward by the gap
+fn resolve_local(tz: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.with_timezone(&Utc),
+        LocalResult::None => {
+            let offset = tz
+                .offset_from_utc_datetime(&(local - TimeDelta::days(1)))
+                .fix();
+            (local - TimeDelta::seconds(offset.local_minus_utc() as i64)).and_utc()
+        }
+    }
+}
+
+/// first fire strictly after `after`, `cron` is evaluated on the wall clock of `timezone`
+pub(crate) fn next_fire(
+    cron: &str,
+    timezone: &str,
+    after: DateTime<Utc>,
+) -> anyhow::Result<DateTime<Utc>> {
+    let schedule = cron::Schedule::from_str(cron).context("Invalid cron")?;
+    let tz = Tz::from_str(timezone).map_err(|e| anyhow::anyhow!("Invalid timezone: {}", e))?;
+    // NOTE: evaluated in naive time, since `cron` drops fires at ambiguous or skipped times
+    let local = after.with_timezone(&tz).naive_local().and_utc();
+    schedule
+        .after(&local)
+        .take(MAX_CANDIDATES)
+        .map(|candidate| resolve_local(tz, candidate.naive_utc()))
+        .find(|fire| *fire > after)
+        .context("Failed to get next cron")
+}
+
+/// fires due at `now` and the next fire after them
+fn due_fires(
+    schedule: &Schedule,
+    now: DateTime<Utc>,
+) -> anyhow::Result<(Vec<DateTime<Utc>>, DateTime<Utc>)> {
+    let next = |after| next_fire(&schedule.cron, &schedule.timezone, after);
+    let mut fire = match schedule.missed_fire_policy {
+        MissedFirePolicy::Skip if schedule.next_fire_at < now - MISFIRE_GRACE => {
+            next(now - MISFIRE_GRACE - TimeDelta::seconds(1))?
+        }
+        _ => schedule.next_fire_at,
+    };
+    let mut fires = vec![];
+    while fire <= now && fires.len() < MAX_FIRES {
+        fires.push(fire);
+        fire = next(fire)?;
+    }
+    if fire <= now {
+        tracing::warn!(
+            "schedule: {} has more than {} missed fires, skipped the rest",
+            schedule.id,
+            MAX_FIRES
+        );
+        fire = next(now)?;
+    }
+    Ok((fires, fire))
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScheduleInput {
+    pub(crate) cron: String,
+    #[serde(default = "default_timezone")]
+    pub(crate) timezone: String,
+    pub(crate) args: Args,
+    pub(crate) retry_policy: Option<RetryPolicy>,
+    pub(crate) timeout_sec: Option<i32>,
+    #[serde(default = "default_enabled")]
+    pub(crate) enabled: bool,
+    pub(crate) missed_fire_policy: Option<MissedFirePolicy>,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl ScheduleInput {
+    /// overwrites the schedule and recomputes the next fire from `now`
+    fn apply(self, schedule: &mut Schedule, now: DateTime<Utc>) -> anyhow::Result<(), Error> {
+        if self.timeout_sec.is_some_and(|sec| sec <= 0) {
+            return Err(Error::InvalidInput(anyhow::anyhow!(
+                "timeoutSec must be positive"
+            )));
+        }
+        schedule.next_fire_at =
+            next_fire(&self.cron, &self.timezone, now).map_err(Error::InvalidInput)?;
+        schedule.cron = self.cron;
+        schedule.timezone = self.timezone;
+        schedule.args = serde_json::to_value(self.args).map_err(|e| Error::Other(e.into()))?;
+        schedule.retry_policy = self
+            .retry_policy
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| Error::Other(e.into()))?;
+        schedule.timeout_sec = self.timeout_sec;
+        schedule.enabled = self.enabled;
+        schedule.missed_fire_policy = self.missed_fire_policy.unwrap_or(MissedFirePolicy::Skip);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ScheduleService {
+    schedule_repo: Arc<dyn ScheduleRepo>,
+    task_repo: Arc<dyn TaskRepo>,
+    api_client: Arc<ApiClient>,
+}
+
+impl ScheduleService {
+    pub(crate) fn new(
+        schedule_repo: Arc<dyn ScheduleRepo>,
+        task_repo: Arc<dyn TaskRepo>,
+        api_client: Arc<ApiClient>,
+    ) -> Self {
+        Self {
+            schedule_repo,
+            task_repo,
+            api_client,
+        }
+    }
+
+    pub(crate) async fn create_schedule(
+        &self,
+        input: ScheduleInput,
+    ) -> anyhow::Result<ScheduleId, Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        let now = Utc::now();
+        let mut schedule = Schedule {
+            id: Uuid::new_v4(),
+            user_id: Some(user_id),
+            cron: String::new(),
+            timezone: String::new(),
+            args: serde_json::Value::Null,
+            retry_policy: None,
+            timeout_sec: None,
+            enabled: true,
+            missed_fire_policy: MissedFirePolicy::Skip,
+            last_fire_at: None,
+            next_fire_at: now,
+            created_at: now,
+        };
+        input.apply(&mut schedule, now)?;
+        self.schedule_repo.create(&schedule).await?;
+        Ok(ScheduleId(schedule.id))
+    }
+
+    pub(crate) async fn list_schedules(&self) -> anyhow::Result<Vec<Schedule>, Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        Ok(self.schedule_repo.find_by_user(&user_id).await?)
+    }
+
+    /// schedules of other users are treated as not found
+    pub(crate) async fn get_schedule(&self, id: &ScheduleId) -> anyhow::Result<Schedule, Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        let schedule = self.schedule_repo.find_by_id(id).await?;
+        if schedule.user_id != Some(user_id) {
+            return Err(Error::Repo(repos::error::Error::NotFound(
+                "schedule".to_string(),
+                id.0.to_string(),
+            )));
+        }
+        Ok(schedule)
+    }
+
+    pub(crate) async fn update_schedule(
+        &self,
+        id: &ScheduleId,
+        input: ScheduleInput,
+    ) -> anyhow::Result<Schedule, Error> {
+        let mut schedule = self.get_schedule(id).await?;
+        input.apply(&mut schedule, Utc::now())?;
+        self.schedule_repo.update(&schedule).await?;
+        Ok(schedule)
+    }
+
+    pub(crate) async fn delete_schedule(&self, id: &ScheduleId) -> anyhow::Result<(), Error> {
+        self.get_schedule(id).await?;
+        self.schedule_repo.delete(id).await?;
+        Ok(())
+    }
+
+    async fn materialize(&self, schedule: &Schedule, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let (fires, next_fire_at) = due_fires(schedule, now)?;
+        let args: Args = serde_json::from_value(schedule.args.clone())?;
+        for fire in fires.iter() {
+            let mut task = new_task(schedule.user_id, args.clone(), *fire, None);
+            task.retry_policy = schedule.retry_policy.clone();
+            task.timeout_sec = schedule.timeout_sec;
+            task.schedule_id = Some(schedule.id);
+            task.fire_time = Some(*fire);
+            task.timezone = Some(schedule.timezone.clone());
+            if self.task_repo.create_scheduled(&task).await? {
+                tracing::info!(
+                    "schedule: {} fired at {} as task: {}",
+                    schedule.id,
+                    fire,
+                    task.id
+                );
+            }
+        }
+        let advanced = self
+            .schedule_repo
+            .advance(
+                &ScheduleId(schedule.id),
+                schedule.next_fire_at,
+                fires.last().copied(),
+                next_fire_at,
+            )
+            .await?;
+        if !advanced {
+            tracing::debug!("schedule: {} advanced by another worker", schedule.id);
+        }
+        Ok(())
+    }
+
+    /// creates tasks for due fires, safe to run on multiple workers
+    pub(crate) async fn materialize_due_schedules(&self) -> anyhow::Result<(), Error> {
+        let now = Utc::now();
+        for schedule in self.schedule_repo.find_due(now).await? {
+            if let Err(e) = self.materialize(&schedule, now).await {
+                tracing::error!("schedule: {} failed to materialize: {:?}", schedule.id, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(cron: &str, next_fire_at: DateTime<Utc>, policy: MissedFirePolicy) -> Schedule {
+        Schedule {
+            id: Uuid::nil(),
+            user_id: None,
+            cron: cron.to_string(),
+            timezone: "UTC".to_string(),
+            args: serde_json::Value::Null,
+            retry_policy: None,
+            timeout_sec: None,
+            enabled: true,
+            missed_fire_policy: policy,
+            last_fire_at: None,
+            next_fire_at,
+            created_at: next_fire_at,
+        }
+    }
+
+    #[test]
+    fn test_due_fires_catch_up() -> anyhow::Result<()> {
+        let t = |h| Utc.with_ymd_and_hms(2024, 1, 1, h, 0, 0).unwrap();
+        let schedule = schedule("0 0 * * * *", t(1), MissedFirePolicy::CatchUp);
+        let (fires, next) = due_fires(&schedule, t(3) + TimeDelta::minutes(30))?;
+        assert_eq!(fires, vec![t(1), t(2), t(3)]);
+        assert_eq!(next, t(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_due_fires_skip() -> anyhow::Result<()> {
+        let t = |h, m| Utc.with_ymd_and_hms(2024, 1, 1, h, m, 0).unwrap();
+        let schedule = schedule("0 0 * * * *", t(1, 0), MissedFirePolicy::Skip);
+        let (fires, next) = due_fires(&schedule, t(3, 30))?;
+        assert!(fires.is_empty());
+        assert_eq!(next, t(4, 0));
+
+        let (fires, _) = due_fires(&schedule, t(3, 0) + TimeDelta::seconds(30))?;
+        assert_eq!(fires, vec![t(3, 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_fire_in_timezone() -> anyhow::Result<()> {
+        // every weekday at 7:00 in Tokyo
+        let cron = "0 0 7 * * Mon-Fri";
+        let friday = Utc.with_ymd_and_hms(2024, 1, 5, 0, 0, 0).unwrap();
+        let next = next_fire(cron, "Asia/Tokyo", friday)?;
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 1, 7, 22, 0, 0).unwrap());
+        assert!(next_fire(cron, "Mars/Olympus", friday).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_fire_dst() -> anyhow::Result<()> {
+        let tz = "America/New_York";
+        let t = |m, d, h, min| Utc.with_ymd_and_hms(2024, m, d, h, min, 0).unwrap();
+
+        // 2:30 does not exist on 2024-03-10, shifted to 3:30 EDT
+        let cron = "0 30 2 * * *";
+        assert_eq!(next_fire(cron, tz, t(3, 10, 0, 0))?, t(3, 10, 7, 30));
+        assert_eq!(next_fire(cron, tz, t(3, 10, 7, 30))?, t(3, 11, 6, 30));
+
+        // 1:30 occurs twice on 2024-11-03, fired once at 1:30 EDT
+        let cron = "0 30 1 * * *";
+        assert_eq!(next_fire(cron, tz, t(11, 3, 0, 0))?, t(11, 3, 5, 30));
+        assert_eq!(next_fire(cron, tz, t(11, 3, 5, 30))?, t(11, 4, 6, 30));
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@ use repos::{
 };
 use script_runtime::{plugins::botcast_api::BotCastApiPlugin, runtime::ScriptRuntime};
 use std::{collections::BTreeMap, sync::Arc};
+use tokio_util::sync::CancellationToken;
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -56,11 +57,12 @@ impl ScriptService {
         Ok(context?.into_iter().collect())
     }
 
-    #[instrument(skip(self), ret)]
+    #[instrument(skip(self, cancel), ret)]
     pub(crate) async fn run_template(
         &self,
         template: &serde_json::Value,
         parameters: BTreeMap<String, serde_json::Value>,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<serde_json::Value, Error> {
         let me = self.api_client.me().await.map_err(Error::Other)?;
         let user_id: Uuid = me
@@ -74,7 +76,7 @@ impl ScriptService {
         runtime.install_plugin(BotCastApiPlugin::new(self.api_client.clone()));
 
         let res = runtime
-            .run(template, context)
+            .run(template, context, cancel)
             .await
             .map_err(Error::Script)?;
         Ok(res)
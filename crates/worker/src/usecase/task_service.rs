@@ -1,18 +1,28 @@
+use super::current_user_id;
 use super::episode_service::EpisodeService;
+use super::pipeline::Step;
+use super::retry_policy::{Attempt, RetryPolicy};
 use super::script_service::ScriptService;
 use crate::error::Error;
-use crate::worker::use_work_dir;
+use crate::worker::{env_or, use_work_dir};
 use anyhow::Context;
 use api::client::ApiClient;
 use chrono::{DateTime, Utc};
-use repos::entity::{EpisodeId, Task, TaskStatus};
-use repos::repo::TaskRepo;
+use repos::entity::{EpisodeId, Task, TaskId, TaskStatus};
+use repos::repo::{TaskRepo, TaskTypeFilter};
 use std::collections::BTreeMap;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use tracing::instrument;
 use uuid::Uuid;
 
+/// a claimed task returns to pending if its lease is not renewed within this duration
+const LEASE_DURATION: Duration = Duration::from_secs(60);
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(20);
+/// timeout of tasks whose default timeout cannot be resolved
+const FALLBACK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
@@ -24,24 +34,69 @@ pub(crate) enum Args {
         template: serde_json::Value,
         parameters: BTreeMap<String, serde_json::Value>,
     },
+    /// runs steps in order, each step can take the results of previous steps
+    Pipeline {
+        steps: Vec<Step>,
+    },
+}
+
+impl Args {
+    /// used unless the task has its own `timeout_sec`
+    fn default_timeout(&self) -> anyhow::Result<Duration> {
+        let timeout_sec = match self {
+            Args::GenerateAudio { .. } => env_or("TASK_TIMEOUT_GENERATE_AUDIO_SEC", 60 * 60)?,
+            Args::EvaluateTemplate { .. } => env_or("TASK_TIMEOUT_EVALUATE_TEMPLATE_SEC", 10 * 60)?,
+            Args::Pipeline { .. } => env_or("TASK_TIMEOUT_PIPELINE_SEC", 2 * 60 * 60)?,
+        };
+        Ok(Duration::from_secs(timeout_sec))
+    }
+}
+
+/// records a failed attempt in `tasks.errors`
+fn push_error(
+    task: &mut Task,
+    error: &Error,
+    finished_at: DateTime<Utc>,
+) -> anyhow::Result<(), Error> {
+    let attempt = Attempt {
+        attempt: task.attempts as u32,
+        executed_at: task.executed_at,
+        finished_at,
+        error: error.to_string(),
+    };
+    let mut errors: Vec<serde_json::Value> =
+        serde_json::from_value(task.errors.clone()).unwrap_or_default();
+    errors.push(serde_json::to_value(attempt).map_err(|e| Error::Other(e.into()))?);
+    task.errors = serde_json::Value::Array(errors);
+    Ok(())
 }
 
 pub(crate) fn new_task(
     user_id: Option<Uuid>,
-    cron: Option<String>,
     args: Args,
     execute_after: DateTime<Utc>,
+    retry_policy: Option<&RetryPolicy>,
 ) -> Task {
     Task {
         id: Uuid::new_v4(),
         user_id,
         status: TaskStatus::Pending,
-        cron,
+        cron: None,
         args: serde_json::to_value(args).unwrap(),
         result: None,
         execute_after,
         executed_at: None,
         executed_finished_at: None,
+        worker_id: None,
+        lease_expires_at: None,
+        attempts: 0,
+        retry_policy: retry_policy.map(|p| serde_json::to_value(p).unwrap()),
+        timeout_sec: None,
+        schedule_id: None,
+        fire_time: None,
+        timezone: None,
+        errors: serde_json::json!([]),
+        step_results: serde_json::json!([]),
     }
 }
 
@@ -68,30 +123,33 @@ impl TaskService {
         }
     }
 
-    #[instrument(skip(self))]
-    async fn execute(&self, task: &Task) -> anyhow::Result<serde_json::Value, Error> {
+    #[instrument(skip(self, cancel))]
+    async fn execute(
+        &self,
+        task: &Task,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<serde_json::Value, Error> {
         let args: Args = serde_json::from_value(task.args.clone())
             .map_err(|e| Error::InvalidInput(anyhow::anyhow!("Args {}", e)))?;
-
-        if let Some(cron) = &task.cron {
-            let next = cron::Schedule::from_str(cron)
-                .context("Invalid cron")
-                .map_err(Error::Other)?
-                .upcoming(Utc)
-                .next()
-                .context("Failed to get next cron")
-                .map_err(Error::Other)?;
-            let task = new_task(task.user_id, Some(cron.to_string()), args.clone(), next);
-            self.task_repo.create(&task).await?;
+        match args {
+            Args::Pipeline { steps } => self.run_pipeline(task, steps, cancel).await,
+            args => self.execute_args(task, args, cancel).await,
         }
+    }
 
+    async fn execute_args(
+        &self,
+        task: &Task,
+        args: Args,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<serde_json::Value, Error> {
         match args {
             Args::GenerateAudio { episode_id } => {
                 let work_dir = use_work_dir(&task.id)
                     .context("Failed to create work dir")
                     .map_err(Error::Other)?;
                 self.episode_service
-                    .generate_audio(&work_dir, &episode_id)
+                    .generate_audio(&work_dir, &episode_id, cancel)
                     .await?;
                 Ok(serde_json::Value::String("OK".to_string()))
             }
@@ -101,53 +159,296 @@ impl TaskService {
             } => {
                 let result = self
                     .script_service
-                    .run_template(&template, parameters)
+                    .run_template(&template, parameters, cancel)
                     .await?;
                 Ok(result)
             }
+            Args::Pipeline { .. } => Err(Error::InvalidInput(anyhow::anyhow!(
+                "Nested pipeline is not supported"
+            ))),
         }
     }
 
-    async fn run_task(&self, mut task: Task) -> anyhow::Result<(), Error> {
-        task.status = TaskStatus::Running;
-        task.executed_at = Some(Utc::now());
-        self.task_repo.update(&task).await?;
-        (task.status, task.result) = match self.execute(&task).await {
-            Ok(result) => (TaskStatus::Completed, Some(result)),
-            Err(e) => (
-                TaskStatus::Failed,
-                Some(serde_json::Value::String(e.to_string())),
-            ),
+    /// resumes after the steps completed by previous attempts,
+    /// later steps are not run once a step fails
+    async fn run_pipeline(
+        &self,
+        task: &Task,
+        steps: Vec<Step>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<serde_json::Value, Error> {
+        let worker_id = task.worker_id.clone().unwrap_or_default();
+        let mut progress = task.clone();
+        let mut results: Vec<serde_json::Value> =
+            serde_json::from_value(task.step_results.clone()).unwrap_or_default();
+        for (i, step) in steps.iter().enumerate().skip(results.len()) {
+            if cancel.is_cancelled() {
+                return Err(Error::Other(anyhow::anyhow!(
+                    "Cancelled after {}/{} steps",
+                    i,
+                    steps.len()
+                )));
+            }
+            let context = format!("step {}/{}", i + 1, steps.len());
+            let args: Args = serde_json::from_value(step.bind(&results)?)
+                .map_err(|e| Error::InvalidInput(anyhow::anyhow!("Args {}", e)))
+                .map_err(|e| e.context(context.clone()))?;
+            tracing::info!("task: {} {}", task.id, context);
+            let value = self
+                .execute_args(task, args, cancel)
+                .await
+                .map_err(|e| e.context(context))?;
+
+            results.push(value);
+            progress.step_results = serde_json::Value::Array(results.clone());
+            if !self.task_repo.update_claimed(&progress, &worker_id).await? {
+                return Err(Error::Other(anyhow::anyhow!(
+                    "Task is no longer claimed after {}/{} steps",
+                    i + 1,
+                    steps.len()
+                )));
+            }
+        }
+        Ok(results.last().cloned().unwrap_or(serde_json::Value::Null))
+    }
+
+    fn timeout(&self, task: &Task) -> Duration {
+        if let Some(timeout_sec) = task.timeout_sec {
+            return Duration::from_secs(timeout_sec.max(0) as u64);
+        }
+        serde_json::from_value::<Args>(task.args.clone())
+            .map_err(anyhow::Error::from)
+            .and_then(|args| args.default_timeout())
+            .unwrap_or_else(|e| {
+                tracing::warn!("task: {} use fallback timeout: {}", task.id, e);
+                FALLBACK_TIMEOUT
+            })
+    }
+
+    /// renews the lease of the task until it is taken over by others
+    async fn keep_lease(&self, task_id: &TaskId, worker_id: &str) -> Error {
+        let mut interval = tokio::time::interval(LEASE_RENEW_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match self
+                .task_repo
+                .renew_lease(task_id, worker_id, Utc::now() + LEASE_DURATION)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Error::Other(anyhow::anyhow!("Lease of task {} is lost", task_id.0))
+                }
+                Err(e) => tracing::warn!("Failed to renew lease of task {}: {}", task_id.0, e),
+            }
+        }
+    }
+
+    pub(crate) async fn run_task(
+        &self,
+        mut task: Task,
+        worker_id: &str,
+    ) -> anyhow::Result<(), Error> {
+        let task_id = TaskId(task.id);
+        let timeout = self.timeout(&task);
+        let deadline = tokio::time::Instant::now() + timeout;
+        let cancel = CancellationToken::new();
+        let result = {
+            let execute = self.execute(&task, &cancel);
+            tokio::pin!(execute);
+            tokio::select! {
+                result = &mut execute => result,
+                // NOTE: dropping `execute` kills running ffmpeg processes
+                _ = tokio::time::sleep_until(deadline) => Err(Error::Timeout(timeout)),
+                e = self.keep_lease(&task_id, worker_id) => {
+                    // NOTE: the lease is also lost when the task is cancelled via api
+                    let task = self.task_repo.find_by_id(&task_id).await?;
+                    if task.status != TaskStatus::Cancelled {
+                        return Err(e);
+                    }
+                    tracing::info!("task: {} cancelling", task_id.0);
+                    cancel.cancel();
+                    tokio::time::timeout_at(deadline, execute)
+                        .await
+                        .unwrap_or(Err(Error::Timeout(timeout)))
+                }
+            }
         };
-        task.executed_finished_at = Some(Utc::now());
+        if cancel.is_cancelled() {
+            return self.finish_cancelled(task, result).await;
+        }
+        let now = Utc::now();
+        // NOTE: pipelines record the progress of steps while running
+        task.step_results = self.task_repo.find_by_id(&task_id).await?.step_results;
+        task.attempts += 1;
+        task.executed_finished_at = Some(now);
+        task.lease_expires_at = None;
+
+        match result {
+            Ok(value) => {
+                task.status = TaskStatus::Completed;
+                task.result = Some(value);
+                tracing::info!("task: {} completed", task.id);
+            }
+            Err(e) => {
+                push_error(&mut task, &e, now)?;
+                // NOTE: tasks without a retry policy are not retried
+                let delay = task
+                    .retry_policy
+                    .clone()
+                    .and_then(|policy| serde_json::from_value::<RetryPolicy>(policy).ok())
+                    .and_then(|policy| policy.next_delay(task.attempts as u32));
+                match delay {
+                    Some(delay) if !e.is_permanent() => {
+                        task.status = TaskStatus::Pending;
+                        task.execute_after = now + delay;
+                        task.worker_id = None;
+                        tracing::warn!(
+                            "task: {} attempt {} failed, retry after {:?}: {}",
+                            task.id,
+                            task.attempts,
+                            delay,
+                            e
+                        );
+                    }
+                    _ => {
+                        task.status = TaskStatus::Failed;
+                        tracing::error!("task: {} failed: {}", task.id, e);
+                    }
+                }
+            }
+        }
+        if !self.task_repo.update_claimed(&task, worker_id).await? {
+            tracing::warn!("task: {} was cancelled, result discarded", task.id);
+        }
+        Ok(())
+    }
+
+    /// records the partial progress of the cancelled task
+    async fn finish_cancelled(
+        &self,
+        mut task: Task,
+        result: anyhow::Result<serde_json::Value, Error>,
+    ) -> anyhow::Result<(), Error> {
+        let cancelled = self.task_repo.find_by_id(&TaskId(task.id)).await?;
+        task.status = TaskStatus::Cancelled;
+        task.attempts += 1;
+        task.step_results = cancelled.step_results;
+        match result {
+            Ok(value) => task.result = Some(value),
+            Err(e) => push_error(
+                &mut task,
+                &e,
+                cancelled.executed_finished_at.unwrap_or_else(Utc::now),
+            )?,
+        }
+        task.executed_finished_at = cancelled.executed_finished_at;
+        task.worker_id = None;
+        task.lease_expires_at = None;
         self.task_repo.update(&task).await?;
-        tracing::info!("task: {} completed", task.id);
+        tracing::info!("task: {} cancelled", task.id);
         Ok(())
     }
 
-    pub(crate) async fn create_task(&self, args: Args) -> anyhow::Result<(), Error> {
-        let user = self
-            .api_client
-            .me()
-            .await
-            .context("Failed to get user")
-            .map_err(Error::Other)?;
-        let user_id = user
-            .id
-            .parse()
-            .context("Failed to parse user id")
-            .map_err(Error::Other)?;
-        let task = new_task(Some(user_id), None, args, Utc::now());
+    pub(crate) async fn create_task(
+        &self,
+        args: Args,
+        retry_policy: Option<RetryPolicy>,
+        timeout_sec: Option<i32>,
+    ) -> anyhow::Result<TaskId, Error> {
+        if timeout_sec.is_some_and(|sec| sec <= 0) {
+            return Err(Error::InvalidInput(anyhow::anyhow!(
+                "timeoutSec must be positive"
+            )));
+        }
+        let user_id = current_user_id(&self.api_client).await?;
+        let mut task = new_task(Some(user_id), args, Utc::now(), retry_policy.as_ref());
+        task.timeout_sec = timeout_sec;
         self.task_repo.create(&task).await?;
+        Ok(TaskId(task.id))
+    }
+
+    /// tasks of other users are treated as not found
+    pub(crate) async fn get_task(&self, task_id: &TaskId) -> anyhow::Result<Task, Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        let task = self.task_repo.find_by_id(task_id).await?;
+        if task.user_id != Some(user_id) {
+            return Err(Error::Repo(repos::error::Error::NotFound(
+                "task".to_string(),
+                task_id.0.to_string(),
+            )));
+        }
+        Ok(task)
+    }
+
+    pub(crate) async fn list_tasks(
+        &self,
+        status: Option<TaskStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> anyhow::Result<Vec<Task>, Error> {
+        let user_id = current_user_id(&self.api_client).await?;
+        let tasks = self
+            .task_repo
+            .find_by_user(&user_id, status, limit, offset)
+            .await?;
+        Ok(tasks)
+    }
+
+    pub(crate) async fn cancel_task(&self, task_id: &TaskId) -> anyhow::Result<Task, Error> {
+        let task = self.get_task(task_id).await?;
+        if !self.task_repo.cancel(task_id, Utc::now()).await? {
+            return Err(Error::InvalidInput(anyhow::anyhow!(
+                "task {} is {:?}, not pending or running",
+                task_id.0,
+                task.status
+            )));
+        }
+        tracing::info!("task: {} cancelled", task_id.0);
+        Ok(self.task_repo.find_by_id(task_id).await?)
+    }
+
+    pub(crate) async fn retry_task(&self, task_id: &TaskId) -> anyhow::Result<Task, Error> {
+        let task = self.get_task(task_id).await?;
+        if !self.task_repo.requeue(task_id, Utc::now()).await? {
+            return Err(Error::InvalidInput(anyhow::anyhow!(
+                "task {} is {:?}, not failed",
+                task_id.0,
+                task.status
+            )));
+        }
+        tracing::info!("task: {} re-queued", task_id.0);
+        Ok(self.task_repo.find_by_id(task_id).await?)
+    }
+
+    pub(crate) async fn reap_expired_tasks(&self) -> anyhow::Result<(), Error> {
+        for task_id in self.task_repo.reap_expired(Utc::now()).await? {
+            tracing::warn!("task: {} lease expired, returned to pending", task_id.0);
+        }
         Ok(())
     }
 
-    pub(crate) async fn execute_queued_tasks(&self) -> anyhow::Result<(), Error> {
-        let Some(task) = self.task_repo.pop(Utc::now()).await? else {
-            return Ok(());
-        };
-        tracing::info!("Found task: {} args={}", task.id, task.args);
-        self.run_task(task).await?;
+    pub(crate) async fn release_tasks(&self, worker_id: &str) -> anyhow::Result<(), Error> {
+        for task_id in self.task_repo.release(worker_id).await? {
+            tracing::warn!("task: {} unfinished, returned to pending", task_id.0);
+        }
         Ok(())
     }
+
+    pub(crate) async fn claim_task(
+        &self,
+        worker_id: &str,
+        filter: &TaskTypeFilter,
+    ) -> anyhow::Result<Option<Task>, Error> {
+        let now = Utc::now();
+        let task = self
+            .task_repo
+            .pop(worker_id, now, now + LEASE_DURATION, filter)
+            .await?;
+        if let Some(task) = &task {
+            tracing::info!("Found task: {} args={}", task.id, task.args);
+        }
+        Ok(task)
+    }
 }
@@ -1,8 +1,19 @@
-use crate::usecase::provider::Provider;
+use crate::usecase::{provider::Provider, task_service::TaskService};
 use audio_generator::workdir::WorkDir;
+use repos::{
+    postgres::{PG_POOL, TASKS_PENDING_CHANNEL},
+    repo::TaskTypeFilter,
+};
+use sqlx::postgres::PgListener;
 use std::{sync::Arc, time::Duration};
+use tokio::sync::{watch, Notify, Semaphore};
 use uuid::Uuid;
 
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `type` tags of `Args` which synthesize audio
+const AUDIO_TASK_TYPES: [&str; 1] = ["generateAudio"];
+
 pub(crate) fn use_work_dir(task_id: &Uuid) -> anyhow::Result<WorkDir> {
     let keep = std::env::var("KEEP_WORKDIR")
         .unwrap_or("false".to_string())
@@ -10,18 +21,225 @@ pub(crate) fn use_work_dir(task_id: &Uuid) -> anyhow::Result<WorkDir> {
     WorkDir::new(task_id, keep)
 }
 
-pub fn start_worker(provider: Arc<Provider>) {
+/// identifies this process in claimed tasks
+pub(crate) fn worker_id() -> String {
+    std::env::var("WORKER_ID").unwrap_or_else(|_| format!("worker-{}", Uuid::new_v4()))
+}
+
+pub(crate) fn env_or<T: std::str::FromStr>(key: &str, default: T) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(key) {
+        Ok(value) => Ok(value.parse()?),
+        Err(_) => Ok(default),
+    }
+}
+
+/// tasks in a lane are claimed and executed independently of other lanes,
+/// so long audio generations do not block script evaluations
+#[derive(Debug, Clone)]
+struct Lane {
+    name: &'static str,
+    filter: TaskTypeFilter,
+    concurrency: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// max number of tasks running at once across all lanes
+    pub concurrency: usize,
+    pub audio_concurrency: usize,
+    /// how long running tasks are waited for on shutdown
+    pub shutdown_timeout: Duration,
+}
+
+impl WorkerConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            concurrency: env_or("WORKER_CONCURRENCY", 4)?,
+            audio_concurrency: env_or("WORKER_AUDIO_CONCURRENCY", 1)?,
+            shutdown_timeout: Duration::from_secs(env_or("WORKER_SHUTDOWN_TIMEOUT_SEC", 30)?),
+        })
+    }
+
+    fn lanes(&self) -> Vec<Lane> {
+        let audio_types: Vec<String> = AUDIO_TASK_TYPES.iter().map(ToString::to_string).collect();
+        vec![
+            Lane {
+                name: "audio",
+                filter: TaskTypeFilter::Only(audio_types.clone()),
+                concurrency: self.audio_concurrency,
+            },
+            Lane {
+                name: "default",
+                filter: TaskTypeFilter::Except(audio_types),
+                concurrency: self.concurrency,
+            },
+        ]
+    }
+}
+
+async fn listen_pending_tasks(notify: &Notify) -> anyhow::Result<()> {
+    let mut listener = PgListener::connect_with(&PG_POOL).await?;
+    listener.listen(TASKS_PENDING_CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        tracing::debug!("task: {} pending", notification.payload());
+        notify.notify_waiters();
+    }
+}
+
+async fn run_lane(
+    task_service: TaskService,
+    worker_id: Arc<String>,
+    lane: Lane,
+    pool: Arc<Semaphore>,
+    notify: Arc<Notify>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let lane_pool = Arc::new(Semaphore::new(lane.concurrency));
+    loop {
+        let notified = notify.notified();
+        let permits = async {
+            let lane_permit = lane_pool.clone().acquire_owned().await.unwrap();
+            let permit = pool.clone().acquire_owned().await.unwrap();
+            (permit, lane_permit)
+        };
+        let (permit, lane_permit) = tokio::select! {
+            permits = permits => permits,
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        if *shutdown.borrow() {
+            break;
+        }
+
+        match task_service.claim_task(&worker_id, &lane.filter).await {
+            Ok(Some(task)) => {
+                let (task_service, worker_id) = (task_service.clone(), worker_id.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = task_service.run_task(task, &worker_id).await {
+                        tracing::error!("Error: {:?}", e);
+                    }
+                    drop((permit, lane_permit));
+                });
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("[{}] Error: {:?}", lane.name, e),
+        }
+        drop((permit, lane_permit));
+
+        tokio::select! {
+            _ = notified => {},
+            _ = tokio::time::sleep(POLL_INTERVAL) => {},
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+    }
+    tracing::info!("[{}] stopped claiming tasks", lane.name);
+}
+
+pub struct Worker {
+    task_service: TaskService,
+    worker_id: Arc<String>,
+    pool: Arc<Semaphore>,
+    config: WorkerConfig,
+}
+
+impl Worker {
+    /// waits running tasks up to `shutdown_timeout` and returns unfinished ones to pending.
+    /// lanes must already be stopped by the shutdown signal
+    pub async fn drain(self) -> anyhow::Result<()> {
+        let running = self.config.concurrency - self.pool.available_permits();
+        tracing::info!("Waiting {} running tasks", running);
+        let all = self.pool.acquire_many(self.config.concurrency as u32);
+        if tokio::time::timeout(self.config.shutdown_timeout, all)
+            .await
+            .is_err()
+        {
+            tracing::warn!("Shutdown timeout exceeded");
+        }
+        self.task_service.release_tasks(&self.worker_id).await?;
+        Ok(())
+    }
+}
+
+/// starts claiming tasks until `shutdown` turns true
+pub fn start_worker(
+    provider: Arc<Provider>,
+    config: WorkerConfig,
+    shutdown: watch::Receiver<bool>,
+) -> Worker {
+    let task_service = provider.task_service();
+    let worker_id = Arc::new(worker_id());
+    tracing::info!("Worker id: {} {:?}", worker_id, config);
+
+    let notify = Arc::new(Notify::new());
+    let pool = Arc::new(Semaphore::new(config.concurrency));
+    for lane in config.lanes() {
+        tokio::spawn(run_lane(
+            task_service.clone(),
+            worker_id.clone(),
+            lane,
+            pool.clone(),
+            notify.clone(),
+            shutdown.clone(),
+        ));
+    }
+
     tokio::spawn(async move {
-        let task_service = provider.task_service();
-        let interval = Duration::from_secs(5);
+        loop {
+            if let Err(e) = listen_pending_tasks(&notify).await {
+                tracing::error!("Failed to listen tasks: {:?}", e);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
 
+    let schedule_service = provider.schedule_service();
+    let mut scheduler_shutdown = shutdown.clone();
+    tokio::spawn(async move {
         loop {
-            // tracing::info!("Watching tasks...");
+            if let Err(e) = schedule_service.materialize_due_schedules().await {
+                tracing::error!("Error: {:?}", e);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {},
+                _ = scheduler_shutdown.wait_for(|stop| *stop) => break,
+            }
+        }
+    });
 
-            if let Err(e) = task_service.execute_queued_tasks().await {
+    let reaper = task_service.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = reaper.reap_expired_tasks().await {
                 tracing::error!("Error: {:?}", e);
             }
-            tokio::time::sleep(interval).await;
+            tokio::time::sleep(POLL_INTERVAL).await;
         }
     });
+
+    Worker {
+        task_service,
+        worker_id,
+        pool,
+        config,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usecase::task_service::Args;
+    use repos::entity::EpisodeId;
+
+    #[test]
+    fn test_audio_task_types() {
+        let args = Args::GenerateAudio {
+            episode_id: EpisodeId(Uuid::nil()),
+        };
+        let args = serde_json::to_value(args).unwrap();
+        assert_eq!(args["type"], AUDIO_TASK_TYPES[0]);
+    }
 }